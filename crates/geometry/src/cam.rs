@@ -1,6 +1,7 @@
 use super::{Bocs, Vec2};
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct Camera {
     pub zoom: f32,
     pub center: Vec2,
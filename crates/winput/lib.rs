@@ -36,8 +36,174 @@ use winit::*;
 const NUM_KEYS: usize = 161;
 const NUM_MOUSE_BUTTONS: usize = 256 + 3;
 
+/// Every key in the order of its index in [Keys]
+const ALL_KEYS: [VirtualKeyCode; NUM_KEYS] = [
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F3,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F9,
+    VirtualKeyCode::F10,
+    VirtualKeyCode::F11,
+    VirtualKeyCode::F12,
+    VirtualKeyCode::F13,
+    VirtualKeyCode::F14,
+    VirtualKeyCode::F15,
+    VirtualKeyCode::F16,
+    VirtualKeyCode::F17,
+    VirtualKeyCode::F18,
+    VirtualKeyCode::F19,
+    VirtualKeyCode::F20,
+    VirtualKeyCode::F21,
+    VirtualKeyCode::F22,
+    VirtualKeyCode::F23,
+    VirtualKeyCode::F24,
+    VirtualKeyCode::Snapshot,
+    VirtualKeyCode::Scroll,
+    VirtualKeyCode::Pause,
+    VirtualKeyCode::Insert,
+    VirtualKeyCode::Home,
+    VirtualKeyCode::Delete,
+    VirtualKeyCode::End,
+    VirtualKeyCode::PageDown,
+    VirtualKeyCode::PageUp,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Compose,
+    VirtualKeyCode::Caret,
+    VirtualKeyCode::Numlock,
+    VirtualKeyCode::Numpad0,
+    VirtualKeyCode::Numpad1,
+    VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6,
+    VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::AbntC1,
+    VirtualKeyCode::AbntC2,
+    VirtualKeyCode::Add,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::Apps,
+    VirtualKeyCode::At,
+    VirtualKeyCode::Ax,
+    VirtualKeyCode::Backslash,
+    VirtualKeyCode::Calculator,
+    VirtualKeyCode::Capital,
+    VirtualKeyCode::Colon,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Convert,
+    VirtualKeyCode::Decimal,
+    VirtualKeyCode::Divide,
+    VirtualKeyCode::Equals,
+    VirtualKeyCode::Grave,
+    VirtualKeyCode::Kana,
+    VirtualKeyCode::Kanji,
+    VirtualKeyCode::LAlt,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::LControl,
+    VirtualKeyCode::LShift,
+    VirtualKeyCode::LWin,
+    VirtualKeyCode::Mail,
+    VirtualKeyCode::MediaSelect,
+    VirtualKeyCode::MediaStop,
+    VirtualKeyCode::Minus,
+    VirtualKeyCode::Multiply,
+    VirtualKeyCode::Mute,
+    VirtualKeyCode::MyComputer,
+    VirtualKeyCode::NavigateForward,
+    VirtualKeyCode::NavigateBackward,
+    VirtualKeyCode::NextTrack,
+    VirtualKeyCode::NoConvert,
+    VirtualKeyCode::NumpadComma,
+    VirtualKeyCode::NumpadEnter,
+    VirtualKeyCode::NumpadEquals,
+    VirtualKeyCode::OEM102,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::PlayPause,
+    VirtualKeyCode::Power,
+    VirtualKeyCode::PrevTrack,
+    VirtualKeyCode::RAlt,
+    VirtualKeyCode::RBracket,
+    VirtualKeyCode::RControl,
+    VirtualKeyCode::RShift,
+    VirtualKeyCode::RWin,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Slash,
+    VirtualKeyCode::Sleep,
+    VirtualKeyCode::Stop,
+    VirtualKeyCode::Subtract,
+    VirtualKeyCode::Sysrq,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Underline,
+    VirtualKeyCode::Unlabeled,
+    VirtualKeyCode::VolumeDown,
+    VirtualKeyCode::VolumeUp,
+    VirtualKeyCode::Wake,
+    VirtualKeyCode::WebBack,
+    VirtualKeyCode::WebFavorites,
+    VirtualKeyCode::WebForward,
+    VirtualKeyCode::WebHome,
+    VirtualKeyCode::WebRefresh,
+    VirtualKeyCode::WebSearch,
+    VirtualKeyCode::WebStop,
+    VirtualKeyCode::Yen,
+    VirtualKeyCode::Copy,
+    VirtualKeyCode::Paste,
+    VirtualKeyCode::Cut,
+];
+
 // ---
 
+#[derive(Clone)]
 struct Keys([KeyInput; NUM_KEYS]);
 
 impl fmt::Debug for Keys {
@@ -66,6 +232,7 @@ impl Default for Keys {
 
 // ---
 
+#[derive(Clone)]
 struct MouseButtons([MouseInput; NUM_MOUSE_BUTTONS]);
 
 impl fmt::Debug for MouseButtons {
@@ -94,12 +261,8 @@ impl Default for MouseButtons {
 
 // ---
 
-/// Position of the mouse
-#[derive(Clone, Copy)]
-pub struct MousePosition(i32, i32);
-
 /// Keyboard input as a buttonstate and modifier state
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct KeyInput {
     /// Modifiers pressed while this event occurred
     pub modifiers: ModifiersState,
@@ -108,7 +271,7 @@ pub struct KeyInput {
 }
 
 /// Mouse input as a buttonstate and a modifier state
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MouseInput {
     /// State of the button
     pub state: ElementState,
@@ -116,6 +279,29 @@ pub struct MouseInput {
     pub modifiers: ModifiersState,
 }
 
+/// A single transition between two [Input] states, see [Input::changes_since]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputChange {
+    /// A key was pressed or released, or its modifiers changed
+    Key {
+        /// The key that changed
+        keycode: VirtualKeyCode,
+        /// The new state of the key
+        input: KeyInput,
+    },
+    /// A mouse button was pressed or released, or its modifiers changed
+    MouseButton {
+        /// The button that changed
+        button: MouseButton,
+        /// The new state of the button
+        input: MouseInput,
+    },
+    /// The mouse moved to a new position
+    MousePosition(f32, f32),
+    /// The scroll wheel moved by the given amount
+    MouseWheel(f32),
+}
+
 // ---
 
 impl From<KeyboardInput> for KeyInput {
@@ -132,7 +318,7 @@ impl From<KeyboardInput> for KeyInput {
 /// This struct accumulates input events and allows them to be used throughout the program. Its
 /// main purpose is to resolve issues of multiple keypresses per-frame as well as accumulating
 /// mouse events such as position and mousewheel events.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Input {
     keys_now: Keys,
     keys_before: Keys,
@@ -272,6 +458,83 @@ impl Input {
     pub fn get_mouse_wheel(&self) -> f32 {
         self.mouse_wheel
     }
+
+    // ---
+
+    /// Iterate over the changes that turn `previous` into `self`.
+    ///
+    /// Keep a clone of the input from the previous frame to get the transitions of this frame.
+    /// The mouse wheel is not compared, the amount accumulated in `self` is reported as a single
+    /// change when it is non-zero.
+    /// ```
+    /// use winit::*;
+    /// use winput::Input;
+    ///
+    /// let previous = Input::default();
+    /// let mut input = previous.clone();
+    /// input.register_mouse_position(3f32, 4f32);
+    /// input.register_mouse_wheel(1f32);
+    ///
+    /// let mut copy = previous.clone();
+    /// for change in input.changes_since(&previous) {
+    ///     copy.apply(change);
+    /// }
+    ///
+    /// assert_eq![(3f32, 4f32), copy.get_mouse_position()];
+    /// assert_eq![1f32, copy.get_mouse_wheel()];
+    /// ```
+    pub fn changes_since<'a>(
+        &'a self,
+        previous: &'a Input,
+    ) -> impl Iterator<Item = InputChange> + 'a {
+        let keys = ALL_KEYS
+            .iter()
+            .zip(self.keys_now.0.iter().zip(previous.keys_now.0.iter()))
+            .filter(|(_, (now, before))| now != before)
+            .map(|(keycode, (now, _))| InputChange::Key {
+                keycode: *keycode,
+                input: *now,
+            });
+        let mouse_buttons = self
+            .mouse_buttons_now
+            .0
+            .iter()
+            .zip(previous.mouse_buttons_now.0.iter())
+            .enumerate()
+            .filter(|(_, (now, before))| now != before)
+            .map(|(index, (now, _))| InputChange::MouseButton {
+                button: index_to_mouse_button(index),
+                input: *now,
+            });
+        let position = if self.mouse_now != previous.mouse_now {
+            Some(InputChange::MousePosition(
+                self.mouse_now.0,
+                self.mouse_now.1,
+            ))
+        } else {
+            None
+        };
+        let wheel = if self.mouse_wheel != 0.0 {
+            Some(InputChange::MouseWheel(self.mouse_wheel))
+        } else {
+            None
+        };
+        keys.chain(mouse_buttons).chain(position).chain(wheel)
+    }
+
+    /// Register a change as if the corresponding event had occurred
+    pub fn apply(&mut self, change: InputChange) {
+        match change {
+            InputChange::Key { keycode, input } => {
+                let keycode = keycode as usize;
+                self.keys_before.0[keycode] = self.keys_now.0[keycode];
+                self.keys_now.0[keycode] = input;
+            }
+            InputChange::MouseButton { button, input } => self.register_mouse_input(input, button),
+            InputChange::MousePosition(x, y) => self.register_mouse_position(x, y),
+            InputChange::MouseWheel(y) => self.register_mouse_wheel(y),
+        }
+    }
 }
 
 fn mouse_button_to_index(button: MouseButton) -> usize {
@@ -283,9 +546,117 @@ fn mouse_button_to_index(button: MouseButton) -> usize {
     }
 }
 
+fn index_to_mouse_button(index: usize) -> MouseButton {
+    match index {
+        0 => MouseButton::Left,
+        1 => MouseButton::Right,
+        2 => MouseButton::Middle,
+        _ => MouseButton::Other((index - 3) as u8),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::quickcheck;
+
+    /// Event kind, key or button index, pressed, shift, and mouse coordinates
+    type Event = (u8, u8, bool, bool, i16, i16);
+
+    fn register_events(input: &mut Input, events: &[Event]) {
+        for (kind, index, pressed, shift, x, y) in events.iter().cloned() {
+            let state = if pressed {
+                ElementState::Pressed
+            } else {
+                ElementState::Released
+            };
+            let modifiers = ModifiersState {
+                shift,
+                ..ModifiersState::default()
+            };
+            match kind % 4 {
+                0 => input.register_key(&KeyboardInput {
+                    scancode: 0,
+                    state,
+                    virtual_keycode: Some(ALL_KEYS[index as usize % NUM_KEYS]),
+                    modifiers,
+                }),
+                1 => input.register_mouse_input(
+                    MouseInput { state, modifiers },
+                    index_to_mouse_button(index as usize),
+                ),
+                2 => input.register_mouse_position(f32::from(x), f32::from(y)),
+                _ => input.register_mouse_wheel(f32::from(x)),
+            }
+        }
+    }
+
+    fn same_state(a: &Input, b: &Input) -> bool {
+        a.keys_now.0[..] == b.keys_now.0[..]
+            && a.mouse_buttons_now.0[..] == b.mouse_buttons_now.0[..]
+            && a.mouse_now == b.mouse_now
+            && a.mouse_wheel == b.mouse_wheel
+    }
+
+    quickcheck! {
+        fn applying_changes_reproduces_input(before: Vec<Event>, during: Vec<Event>) -> bool {
+            let mut previous = Input::default();
+            register_events(&mut previous, &before);
+            previous.prepare_for_next_frame();
+
+            let mut input = previous.clone();
+            register_events(&mut input, &during);
+
+            let mut copy = previous.clone();
+            for change in input.changes_since(&previous) {
+                copy.apply(change);
+            }
+            same_state(&input, &copy)
+        }
+
+        fn no_changes_since_self(events: Vec<Event>) -> bool {
+            let mut input = Input::default();
+            register_events(&mut input, &events);
+            input.prepare_for_next_frame();
+            input.changes_since(&input).count() == 0
+        }
+    }
+
+    #[test]
+    fn index_of_every_key_matches_table() {
+        for (index, keycode) in ALL_KEYS.iter().enumerate() {
+            assert_eq![index, *keycode as usize];
+        }
+    }
+
+    #[test]
+    fn changes_are_toggles_in_the_copy() {
+        let previous = Input::default();
+        let mut input = previous.clone();
+        input.register_key(&KeyboardInput {
+            scancode: 0,
+            state: ElementState::Pressed,
+            virtual_keycode: Some(VirtualKeyCode::Q),
+            modifiers: ModifiersState::default(),
+        });
+        input.register_mouse_input(
+            MouseInput {
+                state: ElementState::Pressed,
+                modifiers: ModifiersState::default(),
+            },
+            MouseButton::Other(7),
+        );
+
+        let changes: Vec<_> = input.changes_since(&previous).collect();
+        assert_eq![2, changes.len()];
+
+        let mut copy = previous.clone();
+        for change in changes {
+            copy.apply(change);
+        }
+        assert![copy.is_key_toggled_down(VirtualKeyCode::Q)];
+        assert![copy.is_mouse_button_toggled_down(MouseButton::Other(7))];
+    }
 
     #[test]
     fn tri_state_switch_pressed_released_pressed() {
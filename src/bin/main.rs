@@ -1,15 +1,20 @@
 use crate::game::{Client, GraphicsSettings, Main, Server};
 use clap::{App, Arg, ArgMatches};
-use failure;
-use fast_logger::{debug, Logger};
+use fast_logger::{error, info, Logger};
 use file_rotate::{FileRotate, RotationMode};
-use laminar::Packet;
 use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::path::Path;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use std::{error::Error, fmt, fs, io};
 use universe::{glocals::*, *};
 
+/// How long to wait for the server to welcome us before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Weapons used by the local server, the built-in ones are used when it doesn't exist
+const WEAPONS_PATH: &str = "weapons.toml";
+
 // ---
 
 fn parse_arguments() -> ArgMatches<'static> {
@@ -27,11 +32,6 @@ fn parse_arguments() -> ArgMatches<'static> {
         .get_matches()
 }
 
-fn read_config(path: &str) -> Result<Config, failure::Error> {
-    let contents = std::fs::read_to_string(path)?;
-    Ok(toml::from_str(&contents)?)
-}
-
 fn wait_for_threads_to_exit(s: &mut Client) {
     if let Some(ref x) = &s.threads.game_shell_keep_running {
         x.store(false, Ordering::Relaxed);
@@ -72,13 +72,33 @@ fn main() -> io::Result<()> {
         );
         cli.apply_config(config.clone());
 
-        debug![logger, "Sending message to"; "address" => address];
-        cli.network
-            .send(Packet::reliable_unordered(address, vec![65, 66, 67, 68]))
-            .expect("Unable to send message");
-        unimplemented!();
+        info![logger, "Connecting to server"; "address" => address];
+        if let Err(err) = cli.connect_and_wait_for_welcome(address, CONNECT_TIMEOUT) {
+            let reason = err.to_string();
+            error![logger, "Unable to connect to server"; "address" => address, "reason" => reason; clone reason];
+            wait_for_threads_to_exit(&mut cli);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, reason));
+        }
+
+        let mut main = Main::new(Some(cli), None, logger.clone());
+        main.entry_point();
+
+        if let Some(ref mut cli) = main.cli.take() {
+            wait_for_threads_to_exit(cli);
+        }
     } else {
-        // Run client + server
+        // Run client + server, with the built-in weapons unless there is a weapons.toml
+        let weapons = if Path::new(WEAPONS_PATH).exists() {
+            game::read_weapons(WEAPONS_PATH).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!["Unable to read {}: {}", WEAPONS_PATH, err],
+                )
+            })?
+        } else {
+            info![logger, "No weapons file, using the built-in weapons"; "path" => WEAPONS_PATH];
+            game::WeaponRegistry::default()
+        };
         let mut cli = Client::new(
             logger.clone_with_context("client"),
             GraphicsSettings::EnableGraphics,
@@ -86,6 +106,7 @@ fn main() -> io::Result<()> {
         cli.apply_config(config.clone());
         let mut srv = Server::new(logger.clone_with_context("server"));
         srv.apply_config(config.clone());
+        srv.logic.weapons = weapons;
         let mut main = Main::new(Some(cli), Some(srv), logger.clone());
         main.entry_point();
 
@@ -0,0 +1,195 @@
+use crate::game::{Server, ServerSettings};
+use clap::{App, Arg, ArgMatches};
+use fast_logger::{error, info, Logger};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Instant;
+use universe::{glocals::*, *};
+
+// ---
+
+fn parse_arguments() -> ArgMatches<'static> {
+    App::new("Universe server")
+        .version("0.1.0")
+        .about("Runs a dedicated server without graphics")
+        .arg(
+            Arg::with_name("bind")
+                .short("b")
+                .long("bind")
+                .value_name("ip")
+                .help("Address to listen on")
+                .default_value("0.0.0.0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .value_name("port")
+                .help("Port to listen on, 0 picks any free port")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("path")
+                .help("Configuration file")
+                .default_value("config.toml")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("weapons")
+                .long("weapons")
+                .value_name("path")
+                .help("Weapon definitions, the built-in ones when the default file is missing")
+                .default_value("weapons.toml")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("x,y,z")
+                .help("World seed")
+                .default_value("0,0,0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("world-size")
+                .short("w")
+                .long("world-size")
+                .value_name("widthxheight")
+                .help("Size of the world in cells")
+                .default_value("1000x1000")
+                .takes_value(true),
+        )
+        .get_matches()
+}
+
+fn parse_seed(seed: &str) -> Result<[f32; 3], String> {
+    let parts = seed
+        .split(',')
+        .map(|part| part.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!["Invalid seed {:?}: {}", seed, err])?;
+    match parts[..] {
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(format!["Seed must have three components, got {:?}", seed]),
+    }
+}
+
+fn parse_world_size(size: &str) -> Result<(usize, usize), String> {
+    let parts = size
+        .split('x')
+        .map(|part| part.trim().parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!["Invalid world size {:?}: {}", size, err])?;
+    match parts[..] {
+        [width, height] if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format![
+            "World size must look like 1000x1000, got {:?}",
+            size
+        ]),
+    }
+}
+
+fn parse_settings(matches: &ArgMatches) -> Result<ServerSettings, String> {
+    let bind = matches.value_of("bind").unwrap();
+    let ip: IpAddr = bind
+        .parse()
+        .map_err(|err| format!["Invalid bind address {:?}: {}", bind, err])?;
+    let port = matches.value_of("port").unwrap();
+    let port: u16 = port
+        .parse()
+        .map_err(|err| format!["Invalid port {:?}: {}", port, err])?;
+    let (world_width, world_height) = parse_world_size(matches.value_of("world-size").unwrap())?;
+    Ok(ServerSettings {
+        address: SocketAddr::new(ip, port),
+        world_seed: parse_seed(matches.value_of("seed").unwrap())?,
+        world_width,
+        world_height,
+    })
+}
+
+// ---
+
+fn main() -> io::Result<()> {
+    let mut logger = Logger::spawn_with_writer("server", io::stdout());
+    logger.set_log_level(255);
+
+    let matches = parse_arguments();
+    let settings =
+        parse_settings(&matches).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let config = read_config(matches.value_of("config").unwrap())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+    let weapons_path = matches.value_of("weapons").unwrap();
+    // A missing default file falls back to the built-in weapons, like the client does
+    let weapons = if matches.occurrences_of("weapons") == 0 && !Path::new(weapons_path).exists() {
+        info![logger, "No weapons file, using the built-in weapons"; "path" => weapons_path.to_string()];
+        game::WeaponRegistry::default()
+    } else {
+        game::read_weapons(weapons_path).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!["Unable to read {}: {}", weapons_path, err],
+            )
+        })?
+    };
+
+    let keep_running = Arc::new(AtomicBool::new(true));
+    {
+        let keep_running = keep_running.clone();
+        ctrlc::set_handler(move || keep_running.store(false, Ordering::Release))
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    }
+
+    let mut srv = match Server::with_settings(logger.clone(), settings) {
+        Ok(srv) => srv,
+        Err(err) => {
+            let reason = err.to_string();
+            error![logger, "Unable to start server"; "reason" => reason; clone reason];
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, reason));
+        }
+    };
+    srv.apply_config(config);
+    srv.logic.weapons = weapons;
+    match srv.network.local_addr() {
+        Ok(address) => info![logger, "Server listening"; "address" => address],
+        Err(_) => error![logger, "Server is not bound to an address"],
+    }
+
+    while keep_running.load(Ordering::Acquire) {
+        srv.time = Instant::now();
+        srv.tick_logic();
+        std::thread::sleep(srv.until_next_tick(Instant::now()));
+    }
+
+    info![logger, "Shutting down"; "players" => srv.logic.players.len(), "ticks" => srv.logic.tick];
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_seed_components() {
+        assert_eq![Ok([1.0, -2.5, 3.0]), parse_seed("1, -2.5,3")];
+        assert![parse_seed("1,2").is_err()];
+        assert![parse_seed("a,b,c").is_err()];
+    }
+
+    #[test]
+    fn parse_world_size_dimensions() {
+        assert_eq![Ok((1000, 500)), parse_world_size("1000x500")];
+        assert![parse_world_size("1000").is_err()];
+        assert![parse_world_size("0x10").is_err()];
+    }
+}
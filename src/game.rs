@@ -1,22 +1,103 @@
 use crate::glocals::*;
 use crate::mediators::collision::*;
+use failure::format_err;
 pub use failure::Error;
 use fast_logger::{error, info, Logger};
 use geometry::vec::Vec2;
 use geometry::{boxit::Boxit, grid2d::Grid};
 use laminar::Socket;
 use rand_pcg::Pcg64Mcg;
-use std::net::{Ipv4Addr, SocketAddrV4};
-use std::{time::Instant, vec::Vec};
+use std::net::SocketAddr;
+use std::{
+    time::{Duration, Instant},
+    vec::Vec,
+};
 use vxdraw::{self, *};
 
 pub mod client;
 pub use client::*;
+pub mod conditioner;
+pub use conditioner::*;
+pub mod crater;
+pub use crater::*;
+pub mod interpolation;
+pub use interpolation::*;
+pub mod scoreboard;
+pub use scoreboard::*;
 pub mod server;
 pub use server::*;
+pub mod snapshot;
+pub use snapshot::*;
+pub mod teams;
+pub use teams::*;
+pub mod terrain;
+pub use terrain::*;
+pub mod timestep;
+pub use timestep::*;
+pub mod weapons;
+pub use weapons::*;
 
 pub type Id = u32;
 pub type Reality = u8;
+/// Health of a player that just spawned
+pub const MAX_HEALTH: u32 = 100;
+/// Reality of players that move through cells with value 0
+pub const BLACK_REALITY: u32 = 0;
+/// Reality of players that move through cells with a non-zero value
+pub const WHITE_REALITY: u32 = 1;
+
+/// The reality a terrain cell belongs to. A cell is air to players and bullets of its own reality
+/// and solid to everything else.
+pub fn cell_reality(cell: Reality) -> u32 {
+    if cell == 0 {
+        BLACK_REALITY
+    } else {
+        WHITE_REALITY
+    }
+}
+
+/// Value written to cells that are turned into air of `reality`
+pub fn air_of(reality: u32) -> Reality {
+    if reality == BLACK_REALITY {
+        0
+    } else {
+        255
+    }
+}
+
+/// Value written to cells that bullets of `reality` break into debris. Debris stays solid to
+/// them, so for white bullets it is simply black terrain.
+pub fn debris_of(reality: u32) -> Reality {
+    if reality == BLACK_REALITY {
+        64
+    } else {
+        0
+    }
+}
+
+/// Collision predicate for players and bullets of `reality`
+pub fn solid_for(reality: u32) -> fn(&Reality) -> bool {
+    fn solid_for_black(cell: &Reality) -> bool {
+        cell_reality(*cell) != BLACK_REALITY
+    }
+    fn solid_for_white(cell: &Reality) -> bool {
+        cell_reality(*cell) != WHITE_REALITY
+    }
+    if reality == BLACK_REALITY {
+        solid_for_black
+    } else {
+        solid_for_white
+    }
+}
+
+/// The reality other than `reality`
+pub fn other_reality(reality: u32) -> u32 {
+    if reality == BLACK_REALITY {
+        WHITE_REALITY
+    } else {
+        BLACK_REALITY
+    }
+}
 pub use fast_logger::{InDebug, InDebugPretty};
 
 pub struct Main {
@@ -35,16 +116,24 @@ impl Main {
     }
     pub fn entry_point(&mut self) {
         loop {
+            let mut idle = None;
             if let Some(ref mut cli) = self.cli {
                 cli.time = Instant::now();
                 cli.tick_logic();
                 if cli.logic.should_exit {
+                    cli.disconnect();
                     break;
                 }
+                idle = Some(cli.until_next_frame(Instant::now()));
             }
             if let Some(ref mut srv) = self.srv {
                 srv.time = Instant::now();
                 srv.tick_logic();
+                let until_tick = srv.until_next_tick(Instant::now());
+                idle = Some(idle.map_or(until_tick, |idle: Duration| idle.min(until_tick)));
+            }
+            if let Some(idle) = idle {
+                std::thread::sleep(idle);
             }
         }
     }
@@ -58,12 +147,14 @@ pub struct Bullet {
     pub position: Vec2,
     pub id: u32,
     pub ty: Weapon,
-}
-
-impl Bullet {
-    pub fn get_stats(&self) -> WeaponStats {
-        self.ty.get_stats()
-    }
+    /// Player that fired the bullet, it can't hit its owner
+    pub owner: Id,
+    /// Reality of the owner when the bullet was fired. The bullet passes through air of this
+    /// reality and turns the terrain it hits into it.
+    pub reality: u32,
+    /// Ticks the bullet has been flying. Only tracked by the server, it isn't part of
+    /// snapshots.
+    pub age: usize,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
@@ -72,10 +163,18 @@ pub struct PlayerData {
     pub velocity: Vec2,
     pub id: Id,
     pub curr_weapon: Weapon,
+    /// Ticks until the current weapon can fire again, or until it is reloaded when `ammo` is 0
     pub curr_weapon_cooldown: usize,
+    /// Bullets left in the magazine of the current weapon
+    pub ammo: u32,
+    /// The player dies when this reaches 0
+    pub health: u32,
+    /// World-space angle in radians from the center of the player to the point it aims at
+    pub aim: f32,
     /// Reality in which the player resides. Reality signifies the colour of the air in which the
     /// player resides.
     pub reality: u32,
+    pub team: Team,
 }
 
 impl PlayerData {
@@ -84,79 +183,26 @@ impl PlayerData {
             position,
             velocity: Vec2::null_vec(),
             id,
-            curr_weapon: Weapon::Hellfire,
+            curr_weapon: Weapon::default(),
             curr_weapon_cooldown: 0,
+            // Filled up by the first update without a cooldown
+            ammo: 0,
+            health: MAX_HEALTH,
+            aim: 0.0,
             reality,
+            team: Team::default(),
         }
     }
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
-pub enum Weapon {
-    Hellfire,
-    Ak47,
-}
-
-impl Weapon {
-    pub fn get_stats(self) -> WeaponStats {
-        match self {
-            Weapon::Hellfire => WeaponStats {
-                width: 10,
-                height: 6,
-                animation_block_begin: (0.0, 0.0),
-                animation_block_end: (1.0, 53.0 / 60.0),
-                sprite_width: 6.8,
-                sprite_height: 0.9,
-                destruction: 3,
-                bullet_count: 1,
-                spread: 0.3,
-                speed: 1.0,
-            },
-            Weapon::Ak47 => {
-                (WeaponStats {
-                    width: 1,
-                    height: 1,
-                    animation_block_begin: (0.0, 54.0 / 60.0),
-                    animation_block_end: (4.0 / 679.0, 58.0 / 60.0),
-                    sprite_width: 0.5,
-                    sprite_height: 0.5,
-                    destruction: 1,
-                    bullet_count: 1,
-                    spread: 0.1,
-                    speed: 2.0,
-                })
-            }
-        }
-    }
-}
-
-impl Default for Weapon {
-    fn default() -> Self {
-        Weapon::Hellfire
-    }
-}
-pub struct WeaponStats {
-    pub width: usize,
-    pub height: usize,
-    pub animation_block_begin: (f32, f32),
-    pub animation_block_end: (f32, f32),
-    pub sprite_width: f32,
-    pub sprite_height: f32,
-    pub destruction: i32,
-    pub bullet_count: u8,
-    pub spread: f32,
-    pub speed: f32,
-}
-
 #[derive(Copy, Clone)]
 pub struct Vertex {
     pub pos: [f32; 2],
 }
 
-pub fn random_port_socket(cfg: laminar::Config) -> Socket {
-    let loopback = Ipv4Addr::new(127, 0, 0, 1);
-    let socket = SocketAddrV4::new(loopback, 0);
-    Socket::bind_with_config(socket, cfg).unwrap() // TODO laminar error not compatible with failure?
+pub fn bind_socket(address: SocketAddr, cfg: laminar::Config) -> Result<Socket, Error> {
+    Socket::bind_with_config(address, cfg)
+        .map_err(|err| format_err!["Unable to bind to {}: {:?}", address, err])
 }
 
 static FIREBALLS: &dyntex::ImgData =
@@ -168,6 +214,17 @@ pub fn initialize_grid(s: &mut Grid<Reality>) {
     s.resize(1000, 1000);
 }
 
+/// Generate the world from `seed`, with the bases of both teams carved into it.
+/// Deterministic, so server and clients build identical grids.
+pub fn generate_world(width: usize, height: usize, seed: [f32; 3]) -> Grid<Reality> {
+    let mut grid = crate::mediators::worldgen::generate_terrain(width, height, seed);
+    create_black_square_around_player(&mut grid);
+    for base in Base::for_world(width, height).iter() {
+        base.carve(&mut grid);
+    }
+    grid
+}
+
 pub fn create_black_square_around_player(s: &mut Grid<Reality>) {
     for (i, j) in Boxit::with_center((100, 100), (500, 300)) {
         s.set(i, j, 0);
@@ -175,28 +232,26 @@ pub fn create_black_square_around_player(s: &mut Grid<Reality>) {
 }
 
 pub fn accelerate_player_according_to_input(
-    inp: &winput::Input,
+    actions: &ActionState,
     conf: &WorldConfig,
     on_ground: bool,
 ) -> Vec2 {
-    let dy = if inp.is_key_down(winit::VirtualKeyCode::Up) {
-        if conf.gravity_on {
-            if on_ground {
-                -conf.player.jump_acc
-            } else {
-                0.0
-            }
+    let dy = if conf.gravity_on && actions.is_down(Action::Jump) {
+        if on_ground {
+            -conf.player.jump_acc
         } else {
-            -conf.player.acc
+            0.0
         }
-    } else if inp.is_key_down(winit::VirtualKeyCode::Down) {
+    } else if !conf.gravity_on && actions.is_down(Action::MoveUp) {
+        -conf.player.acc
+    } else if actions.is_down(Action::MoveDown) {
         conf.player.acc
     } else {
         0.0
     };
-    let dx = if inp.is_key_down(winit::VirtualKeyCode::Left) {
+    let dx = if actions.is_down(Action::MoveLeft) {
         -conf.player.acc
-    } else if inp.is_key_down(winit::VirtualKeyCode::Right) {
+    } else if actions.is_down(Action::MoveRight) {
         conf.player.acc
     } else {
         0.0
@@ -204,7 +259,7 @@ pub fn accelerate_player_according_to_input(
     Vec2 {
         x: dx as f32,
         y: dy as f32,
-    } / if inp.is_key_down(winit::VirtualKeyCode::LShift) {
+    } / if actions.is_down(Action::Walk) {
         3.0
     } else {
         1.0
@@ -316,7 +371,7 @@ fn fire_bullets(
 
 fn update_player(
     player: &mut PlayerData,
-    player_input: &winput::Input,
+    actions: &ActionState,
     config: &WorldConfig,
     random: &mut Pcg64Mcg,
     grid: &Grid<Reality>,
@@ -330,7 +385,7 @@ fn update_player(
     let on_ground =
         check_for_collision_and_move_player_according_to_movement_vector(grid, player, logger);
 
-    let acc = accelerate_player_according_to_input(player_input, config, on_ground);
+    let acc = accelerate_player_according_to_input(actions, config, on_ground);
     player.velocity += acc;
 
     player.velocity = player.velocity.clamp(Vec2 {
@@ -352,6 +407,7 @@ fn check_for_collision_and_move_player_according_to_movement_vector(
     _logger: &mut Logger<Log>,
 ) -> bool {
     let movement = player.velocity;
+    let solid = solid_for(player.reality);
     let tl = Vec2 {
         x: player.position.x + 0.01,
         y: player.position.y + 0.01,
@@ -374,9 +430,7 @@ fn check_for_collision_and_move_player_according_to_movement_vector(
         y: movement.y,
     };
     for i in 1..=10 {
-        collision_y = collision_test(&[tl, tr, br, bl], Some(0.5), ymove / i as f32, grid, |x| {
-            *x > 0
-        });
+        collision_y = collision_test(&[tl, tr, br, bl], Some(0.5), ymove / i as f32, grid, solid);
         if collision_y.is_none() {
             player.position += ymove / i as f32;
             break;
@@ -405,9 +459,7 @@ fn check_for_collision_and_move_player_according_to_movement_vector(
         y: 0.0,
     };
     for i in 1..=10 {
-        collision_x = collision_test(&[tl, tr, br, bl], Some(0.5), xmove / i as f32, grid, |x| {
-            *x > 0
-        });
+        collision_x = collision_test(&[tl, tr, br, bl], Some(0.5), xmove / i as f32, grid, solid);
         if collision_x.is_none() {
             player.position += xmove / i as f32;
             break;
@@ -428,9 +480,10 @@ mod tests {
     use crate::game::{Client, Server};
     use crate::mediators::testtools::*;
     use fast_logger::Logger;
+    use laminar::Packet;
+    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
 
     #[test]
-    #[cfg(feature = "gui-tests")]
     fn basic_setup_and_teardown() {
         Server::new(Logger::spawn_void());
     }
@@ -497,16 +550,330 @@ mod tests {
     // ---
 
     #[test]
-    #[cfg(feature = "gui-tests")]
     fn client_and_server() {
         let lgr = Logger::spawn_void();
         let mut srv = Server::new(lgr.clone());
         let mut cli = Client::new(lgr.clone(), GraphicsSettings::DisableGraphics);
-        cli.connect_to_server(srv.network.local_addr().unwrap());
+        cli.connect_to_server(srv.network.local_addr().unwrap())
+            .expect("Unable to send Join to the server");
 
         srv.tick_logic();
         cli.tick_logic();
 
         Main::new(Some(cli), Some(srv), lgr.clone());
     }
+
+    /// Ticks a server and its clients on simulated time until `done` holds, giving up after
+    /// `max_ticks` ticks
+    fn run_until(
+        srv: &mut Server,
+        clients: &mut [Client],
+        max_ticks: usize,
+        mut done: impl FnMut(&mut Server, &mut [Client]) -> bool,
+    ) -> bool {
+        let mut time = Instant::now();
+        for _ in 0..max_ticks {
+            time += TICK_DURATION;
+            srv.time = time;
+            srv.tick_logic();
+            for cli in clients.iter_mut() {
+                cli.time = time;
+                cli.tick_logic();
+            }
+            if done(srv, clients) {
+                return true;
+            }
+            // Give the loopback interface a moment to deliver what was sent
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        false
+    }
+
+    /// An address of this host other than loopback, `None` without a network interface
+    fn non_loopback_address() -> Option<IpAddr> {
+        // Connecting a UDP socket sends nothing, it only picks the interface to route through
+        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+        socket.connect("192.0.2.1:9").ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        if ip.is_loopback() || ip.is_unspecified() {
+            None
+        } else {
+            Some(ip)
+        }
+    }
+
+    #[test]
+    fn client_connects_through_non_loopback_address() {
+        let ip = match non_loopback_address() {
+            Some(ip) => ip,
+            // Nothing to test on a host without a network
+            None => return,
+        };
+        let lgr = Logger::spawn_void();
+        let settings = ServerSettings {
+            address: "0.0.0.0:0".parse().unwrap(),
+            ..ServerSettings::default()
+        };
+        let mut srv = Server::with_settings(lgr.clone(), settings).unwrap();
+        let port = srv.network.local_addr().unwrap().port();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0]
+            .connect_to_server(SocketAddr::new(ip, port))
+            .unwrap();
+
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].server.is_some()
+        })];
+    }
+
+    #[test]
+    fn clients_see_each_other_on_a_bad_network() {
+        let lgr = Logger::spawn_void();
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(40),
+            jitter: Duration::from_millis(30),
+            loss: 0.2,
+            duplication: 0.1,
+        };
+        let mut srv = Server::new(lgr.clone());
+        srv.network.set_conditions(conditions.clone(), 0);
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients: Vec<Client> = (0..3)
+            .map(|seed| {
+                let mut cli = Client::new(lgr.clone(), GraphicsSettings::DisableGraphics);
+                cli.network.set_conditions(conditions.clone(), seed + 1);
+                cli
+            })
+            .collect();
+
+        let everyone_visible = run_until(&mut srv, &mut clients, 2000, |_, clients| {
+            for cli in clients.iter_mut() {
+                if cli.server.is_none() {
+                    cli.connect_to_server(addr).unwrap();
+                }
+            }
+            clients
+                .iter()
+                .all(|cli| cli.server.is_some() && cli.logic.players.len() == 3)
+        });
+
+        assert![everyone_visible];
+        assert_eq![3, srv.logic.players.len()];
+    }
+
+    #[test]
+    fn weapon_switch_is_seen_by_other_clients() {
+        let lgr = Logger::spawn_void();
+        let mut srv = Server::new(lgr.clone());
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients: Vec<Client> = (0..2)
+            .map(|_| Client::new(lgr.clone(), GraphicsSettings::DisableGraphics))
+            .collect();
+        for cli in clients.iter_mut() {
+            cli.connect_to_server(addr).unwrap();
+        }
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients.iter().all(|cli| cli.logic.players.len() == 2)
+        })];
+
+        let ak47 = srv.logic.weapons.find("Ak47").unwrap();
+        clients[0].switch_weapon(ak47);
+        let id = clients[0].logic.self_id;
+
+        let switched = run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[1]
+                .logic
+                .players
+                .get(&id)
+                .map_or(false, |player| player.curr_weapon == ak47)
+        });
+        assert![switched];
+    }
+
+    #[test]
+    fn client_without_graphics_receives_bullets() {
+        let lgr = Logger::spawn_void();
+        let mut srv = Server::new(lgr.clone());
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0].connect_to_server(addr).unwrap();
+        assert![run_until(&mut srv, &mut clients, 500, |srv, _| {
+            !srv.logic.players.is_empty()
+        })];
+
+        // A bullet resting inside its owner never hits anything
+        let owner = &srv.logic.players[0];
+        srv.logic.bullets.push(Bullet {
+            position: owner.position,
+            id: 1000,
+            owner: owner.id,
+            reality: owner.reality,
+            ..Bullet::default()
+        });
+
+        let received = run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0]
+                .logic
+                .bullets
+                .get(&1000)
+                .map_or(false, |bullet| bullet.handle.is_none())
+        });
+        assert![received];
+    }
+
+    #[test]
+    fn late_joiner_receives_changed_terrain() {
+        let lgr = Logger::spawn_void();
+        let settings = ServerSettings {
+            world_width: 300,
+            world_height: 200,
+            ..ServerSettings::default()
+        };
+        let mut srv = Server::with_settings(lgr.clone(), settings).unwrap();
+        // Terrain that can't be regenerated from the seed, like craters dug before joining
+        for i in 0..300 {
+            srv.logic.grid.set(i, 7, 42);
+        }
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0].connect_to_server(addr).unwrap();
+
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].server.is_some() && clients[0].logic.download.is_none()
+        })];
+        let grid = &clients[0].logic.grid;
+        assert_eq![(300, 200), grid.get_size()];
+        for i in 0..300 {
+            for j in 0..200 {
+                assert_eq![srv.logic.grid.get(i, j), grid.get(i, j)];
+            }
+        }
+    }
+
+    #[test]
+    fn state_from_before_a_removal_is_ignored() {
+        let lgr = Logger::spawn_void();
+        let mut srv = Server::new(lgr.clone());
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0].connect_to_server(addr).unwrap();
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].logic.players.len() == 1
+        })];
+
+        // The removal of a bullet, then a state from before it that arrives late
+        let client_port = clients[0].network.local_addr().unwrap().port();
+        let client_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), client_port);
+        let mut late = ConditionedSocket::new(
+            bind_socket("127.0.0.1:0".parse().unwrap(), laminar::Config::default()).unwrap(),
+        );
+        let removal_tick = srv.logic.tick + 1000;
+        let delta = ServerMessage::DeltaState {
+            removed: vec![(1000, EntityType::Bullet)],
+            grid_changes: Vec::new(),
+            world: clients[0].logic.world,
+            tick: removal_tick,
+        };
+        late.send(Packet::reliable_unordered(client_addr, delta.serialize()))
+            .unwrap();
+        late.manual_poll(Instant::now());
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].logic.removal_tick == removal_tick
+        })];
+
+        let snapshot = Snapshot {
+            players: Vec::new(),
+            bullets: vec![Bullet {
+                id: 1000,
+                ..Bullet::default()
+            }],
+        };
+        let state = ServerMessage::State {
+            snapshot: u32::max_value(),
+            tick: removal_tick - 1,
+            baseline: None,
+            last_input_sequence: 0,
+            data: encode_snapshot(&snapshot, None).unwrap(),
+        };
+        late.send(Packet::unreliable(client_addr, state.serialize()))
+            .unwrap();
+        late.manual_poll(Instant::now());
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0]
+                .logic
+                .received_snapshots
+                .back()
+                .map_or(false, |(number, _)| *number == u32::max_value())
+        })];
+        assert![!clients[0].logic.bullets.contains_key(&1000)];
+    }
+
+    #[test]
+    fn grid_changes_of_previous_world_are_dropped() {
+        let lgr = Logger::spawn_void();
+        let mut srv = Server::new(lgr.clone());
+        srv.logic.world = 1;
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0].connect_to_server(addr).unwrap();
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].server.is_some() && clients[0].logic.download.is_none()
+        })];
+
+        // Like delta states of the previous and the current world arriving late
+        let client_port = clients[0].network.local_addr().unwrap().port();
+        let client_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), client_port);
+        let mut late = ConditionedSocket::new(
+            bind_socket("127.0.0.1:0".parse().unwrap(), laminar::Config::default()).unwrap(),
+        );
+        for (world, x) in [(0, 10), (1, 20)].iter().cloned() {
+            let delta = ServerMessage::DeltaState {
+                removed: Vec::new(),
+                grid_changes: encode_grid_changes(&[(x, 10, 99)]),
+                world,
+                tick: 0,
+            };
+            late.send(Packet::reliable_unordered(client_addr, delta.serialize()))
+                .unwrap();
+        }
+        late.manual_poll(Instant::now());
+
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].logic.grid.get(20, 10) == Some(&99)
+        })];
+        assert_eq![
+            srv.logic.grid.get(10, 10),
+            clients[0].logic.grid.get(10, 10)
+        ];
+    }
+
+    #[test]
+    fn diverged_terrain_is_resynced() {
+        let lgr = Logger::spawn_void();
+        let settings = ServerSettings {
+            world_width: 300,
+            world_height: 200,
+            ..ServerSettings::default()
+        };
+        let mut srv = Server::with_settings(lgr.clone(), settings).unwrap();
+        srv.config.terrain_hash_interval = 0.1;
+        let addr = srv.network.local_addr().unwrap();
+        let mut clients = vec![Client::new(lgr.clone(), GraphicsSettings::DisableGraphics)];
+        clients[0].connect_to_server(addr).unwrap();
+        assert![run_until(&mut srv, &mut clients, 500, |_, clients| {
+            clients[0].server.is_some() && clients[0].logic.download.is_none()
+        })];
+
+        // Like a grid change that never arrived
+        for i in 100..200 {
+            clients[0].logic.grid.set(i, 150, 13);
+        }
+        let same_terrain = |srv: &mut Server, clients: &mut [Client]| {
+            (0..300).all(|i| {
+                (0..200).all(|j| srv.logic.grid.get(i, j) == clients[0].logic.grid.get(i, j))
+            })
+        };
+        assert![!same_terrain(&mut srv, &mut clients)];
+        assert![run_until(&mut srv, &mut clients, 500, same_terrain)];
+    }
 }
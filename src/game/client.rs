@@ -2,33 +2,61 @@ use super::*;
 use crate::mediators::testtools::*;
 use geometry::{cam::Camera, grid2d::Grid, vec::Vec2};
 use indexmap::IndexMap;
-use laminar::{Packet, Socket, SocketEvent};
+use laminar::{Packet, SocketEvent};
 use rand_pcg::Pcg64Mcg;
 use rodio;
-use std::net::SocketAddr;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use winput;
 
 use cgmath::*;
+use failure::format_err;
 use fast_logger::{debug, info, warn, GenericLogger, Logger};
-use std::time::Instant;
-use winit::{ElementState, VirtualKeyCode as Key, *};
+use std::time::{Duration, Instant};
+use winit::{VirtualKeyCode as Key, *};
 use winput::Input;
 
 static PLAYER_CENTER: Vec2 = Vec2 { x: 5.0, y: 5.0 };
+/// Upper bound on unacknowledged inputs kept for replay
+const MAX_PENDING_INPUTS: usize = 256;
+/// Number of decoded snapshots kept as baselines for the deltas sent by the server
+const MAX_RECEIVED_SNAPSHOTS: usize = 64;
+const JOIN_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+/// Fraction of the correction of our own player that is still drawn after a tick
+const CORRECTION_DECAY: f32 = 0.85;
+/// Corrections longer than this, like respawns, are not smoothed out
+const MAX_SMOOTHED_CORRECTION: f32 = 50.0;
+/// Number keys that select the weapon at the same index in the `WeaponRegistry`
+const WEAPON_KEYS: [Key; 9] = [
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+];
 
 pub struct Client {
+    /// Actions held down as of the latest tick
+    pub actions: ActionState,
     pub audio: Option<rodio::Sink>,
     pub config: ClientConfig,
+    pub controls: Controls,
     pub events: Option<winit::EventsLoop>,
     pub graphics: Option<Graphics>,
     pub input: Input,
     pub logger: Logger<Log>,
     pub logic: ClientLogic,
-    pub network: Socket,
+    pub network: ConditionedSocket,
     pub random: Pcg64Mcg,
     pub server: Option<SocketAddr>,
     pub threads: Threads,
+    /// Start of the current frame
     pub time: Instant,
+    pub timestep: FixedTimestep,
 }
 
 #[derive(Default)]
@@ -36,16 +64,53 @@ pub struct ClientLogic {
     pub should_exit: bool,
 
     pub grid: Grid<Reality>,
+    /// Terrain being received from the server, simulation is held off until it is complete
+    pub download: Option<TerrainDownload>,
+    /// Generation of the world the server last told us about
+    pub world: u32,
+    /// Grid changes of a world whose `NewWorld` hasn't arrived yet, with its generation
+    pub early_grid_changes: Vec<(u32, Vec<GridRect>)>,
+    /// Ticks of the `DeltaState` messages received so far
+    pub delta_ticks: ReceivedTicks,
+    /// Latest terrain hashes from the server with the tick they cover, compared once we have
+    /// the grid changes up to that tick
+    pub terrain_hashes: Option<(u64, Vec<Option<u64>>)>,
     pub config: WorldConfig,
     pub players: IndexMap<Id, ClientPlayer>,
     pub bullets: IndexMap<Id, ClientBullet>,
     pub self_id: Id,
 
     pub cam: Camera,
-    pub you: u32,
 
+    /// Sequence number of the last input sent to the server, 0 before the first. The server
+    /// reports 0 as the last processed input until it processed one.
+    pub input_sequence: u32,
+    /// Inputs sent to the server but not yet acknowledged, replayed on top of the authoritative
+    /// state of our own player
+    pub pending_inputs: VecDeque<(u32, ActionState)>,
+    /// Recently decoded snapshots, the server encodes new ones relative to these
+    pub received_snapshots: VecDeque<(u32, Snapshot)>,
+    /// Newest snapshot that has not been acknowledged to the server yet
+    pub unacked_snapshot: Option<u32>,
+    /// Server time estimated from the ticks of received snapshots, remote entities are
+    /// rendered relative to it
+    pub server_clock: ServerClock,
+    /// Server tick of the latest `DeltaState` that removed entities. Snapshots taken before it
+    /// may arrive later and would bring the removed entities back.
+    pub removal_tick: u64,
+    /// Weapon we last asked the server for. Drawn for our own player right away, the server
+    /// confirms it through `PlayerData::curr_weapon`.
+    pub weapon: Weapon,
+    /// Weapons received from the server
+    pub weapons: WeaponRegistry,
+
+    /// Centers of the team bases, derived from the world size
     pub white_base: Vec2,
     pub black_base: Vec2,
+    /// Latest round status received from the server
+    pub round: RoundStatus,
+    /// Latest scoreboard received from the server
+    pub scoreboard: Scoreboard,
 
     // Extra graphics data (for debugging/visualization)
     pub vectors: Vec<(Vec2, Vec2)>,
@@ -60,6 +125,15 @@ pub struct ClientPlayer {
     pub inner: PlayerData,
     pub input: Input,
     pub weapon_sprite: Option<vxdraw::dyntex::Handle>,
+    /// Quad of a remote player. Our own player is drawn with `Graphics::player_quads[0]`
+    pub quad: Option<vxdraw::quads::Handle>,
+    /// Position before the latest tick, our own player is drawn between this and `position`
+    pub previous_position: Vec2,
+    /// Offset of our own player from where it was drawn before the server corrected its
+    /// prediction. Added when drawing and shrunk every tick, so corrections don't snap.
+    pub correction: Vec2,
+    /// Server snapshots, only used for remote players
+    pub snapshots: SnapshotBuffer<PlayerData>,
 }
 
 impl std::ops::Deref for ClientPlayer {
@@ -78,7 +152,10 @@ impl std::ops::DerefMut for ClientPlayer {
 pub struct ClientBullet {
     /// Holds the logical data
     inner: Bullet,
-    pub handle: vxdraw::dyntex::Handle,
+    /// Sprite of the bullet, `None` without graphics
+    pub handle: Option<vxdraw::dyntex::Handle>,
+    /// Server snapshots that `inner` is interpolated from
+    pub snapshots: SnapshotBuffer<Bullet>,
 
     pub animation_sequence: usize,
     pub current_uv_begin: (f32, f32),
@@ -101,10 +178,19 @@ pub enum CameraMode {
 
 pub struct Graphics {
     pub basic_text: vxdraw::text::Handle,
+    pub text_layer: vxdraw::text::Layer,
+    /// Progress of the terrain download, shown while it lasts
+    pub download_text: Option<vxdraw::text::Handle>,
     pub player_quads: Vec<vxdraw::quads::Handle>,
+    pub players_layer: vxdraw::quads::Layer,
     pub bullets_texture: vxdraw::dyntex::Layer,
     pub weapons_texture: vxdraw::dyntex::Layer,
+    /// Terrain layer, created before the other layers so the terrain is drawn below them
     pub grid: vxdraw::strtex::Layer,
+    /// Width and height of `grid` in cells
+    pub grid_size: (usize, usize),
+    /// Sprite that shows `grid`, `None` until a world has been received from the server
+    pub grid_sprite: Option<vxdraw::strtex::Handle>,
     pub windowing: vxdraw::VxDraw,
 }
 
@@ -127,19 +213,24 @@ impl Client {
         logger.set_this_log_level(192);
         let mut cfg = laminar::Config::default();
         cfg.receive_buffer_max_size = cfg.max_packet_size;
+        // Any interface, so servers on other hosts can be reached
+        let address = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
         let mut s = Client {
+            actions: ActionState::default(),
             audio: None,
             config: Default::default(),
+            controls: Controls::default(),
             events: None,
             graphics: None,
             input: Input::default(),
             logger,
             logic: ClientLogic::default(),
-            network: random_port_socket(cfg),
+            network: ConditionedSocket::new(bind_socket(address, cfg).unwrap()),
             random: Pcg64Mcg::new(0),
             server: None,
             threads: Threads::default(),
             time: Instant::now(),
+            timestep: FixedTimestep::new(TICK_DURATION, Instant::now()),
         };
 
         spawn_gameshell(&mut s);
@@ -157,10 +248,18 @@ impl Client {
         s
     }
     /// Assigns `config.client` to `self.config` and `config.world` to `self.logic.config`.
+    /// Also replaces `self.controls` with the bindings from `config.controls`, or keeps the
+    /// current ones if they are invalid.
     pub fn apply_config(&mut self, config: Config) {
         let (s, w) = (config.client, config.world);
         self.config = s;
         self.logic.config = w;
+        match Controls::from_config(&config.controls) {
+            Ok(controls) => self.controls = controls,
+            Err(err) => {
+                error![self.logger, "Invalid controls"; "reason" => err.to_string()];
+            }
+        }
     }
 
     /// Sends a Join request to the server at `addr`.
@@ -168,23 +267,120 @@ impl Client {
     pub fn connect_to_server(&mut self, addr: SocketAddr) -> Result<(), Error> {
         self.network
             .send(Packet::unreliable(addr, ClientMessage::Join.serialize()))
-            .unwrap(); /* TODO!! ? operator doesn't work here */
+            .map_err(|err| format_err!["Unable to send Join to {}: {:?}", addr, err])?;
         info![self.logger, "Sent Join"];
         Ok(())
     }
 
+    /// Sends Join requests to `addr` and ticks until the server answers with a Welcome message.
+    /// Join is resent periodically since it is sent unreliably.
+    pub fn connect_and_wait_for_welcome(
+        &mut self,
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut last_join: Option<Instant> = None;
+        while self.server.is_none() {
+            self.time = Instant::now();
+            if self.time.duration_since(start) > timeout {
+                return Err(format_err![
+                    "No Welcome from server {} within {} seconds",
+                    addr,
+                    timeout.as_secs_f32()
+                ]);
+            }
+            if last_join.map_or(true, |last| {
+                self.time.duration_since(last) > JOIN_RESEND_INTERVAL
+            }) {
+                self.connect_to_server(addr)?;
+                last_join = Some(self.time);
+            }
+            self.tick_logic();
+            if self.logic.should_exit {
+                return Err(format_err!["Exited while connecting to {}", addr]);
+            }
+            std::thread::sleep(self.timestep.until_next_tick(Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// Tells the server that we are leaving, so it can remove our player immediately.
+    pub fn disconnect(&mut self) {
+        if let Some(addr) = self.server.take() {
+            let result = self.network.send(Packet::reliable_unordered(
+                addr,
+                ClientMessage::Leave.serialize(),
+            ));
+            if result.is_err() {
+                error![self.logger, "Failed to send Leave packet"];
+            }
+            self.network.manual_poll(Instant::now());
+            info![self.logger, "Sent Leave"];
+        }
+    }
+
+    /// Runs the simulation ticks that are due and draws a single frame
     pub fn tick_logic(&mut self) {
-        toggle_camera_mode(self);
+        for _ in 0..self.timestep.advance(self.time) {
+            self.tick();
+        }
+
+        if let Some(Ok(msg)) = self
+            .threads
+            .game_shell_channel
+            .as_mut()
+            .map(|x| x.try_recv())
+        {
+            (msg)(self);
+        }
+
+        interpolate_remote_entities(
+            &mut self.logic,
+            self.time,
+            Duration::from_secs_f32(self.config.interpolation_delay.max(0.0)),
+            Duration::from_secs_f32(self.config.max_extrapolation.max(0.0)),
+        );
+
+        update_graphics(self);
+
+        draw_graphics(self);
+    }
+
+    /// Time left of the current frame when honouring `ClientConfig::fps`
+    pub fn until_next_frame(&self, now: Instant) -> Duration {
+        until_next_frame(self.time, now, self.config.fps)
+    }
+
+    /// A single fixed-length simulation step
+    fn tick(&mut self) {
         if let Some(ref mut events) = self.events {
             process_input(&mut self.input, events);
         }
-        self.update_network();
+        toggle_camera_mode(self);
+        // Input is picked up once the terrain is complete, so held keys are sent then
+        let commands = if self.logic.download.is_none() {
+            self.collect_input()
+        } else {
+            Vec::new()
+        };
+        let weapon = self.collect_weapon_switch(&commands);
+        self.update_network(commands);
+        if let Some(weapon) = weapon {
+            self.switch_weapon(weapon);
+        }
         move_camera_according_to_input(self);
+        update_view_size(self);
 
-        if let Some(player) = self.logic.players.get_mut(&self.logic.you) {
+        if let (Some(player), None) = (
+            self.logic.players.get_mut(&self.logic.self_id),
+            &self.logic.download,
+        ) {
+            player.previous_position = player.position;
+            player.correction = player.correction.scale_uni(CORRECTION_DECAY);
             update_player(
                 &mut player.inner,
-                &self.input,
+                &self.actions,
                 &self.logic.config,
                 &mut self.random,
                 &self.logic.grid,
@@ -193,29 +389,13 @@ impl Client {
         }
 
         update_bullets_uv(&mut self.logic);
-        std::thread::sleep(std::time::Duration::new(0, 8_000_000));
 
         set_gravity(self);
 
-        if let Some(Ok(msg)) = self
-            .threads
-            .game_shell_channel
-            .as_mut()
-            .map(|x| x.try_recv())
-        {
-            (msg)(self);
-        }
-
-        handle_mouse_scroll(self);
-
         // fire_bullets(&mut self.logic, &mut self.graphics, &mut self.random);
-
-        update_graphics(self);
-
-        draw_graphics(self);
     }
 
-    fn update_network(&mut self) {
+    fn update_network(&mut self, commands: Vec<InputCommand>) {
         // Process incoming messages
         loop {
             self.network.manual_poll(self.time);
@@ -224,36 +404,119 @@ impl Client {
                     let msg = ServerMessage::deserialize(pkt.payload());
                     if let Ok(msg) = msg {
                         match msg {
-                            ServerMessage::Welcome { your_id, .. } => {
+                            ServerMessage::Welcome {
+                                your_id,
+                                world_width,
+                                world_height,
+                                weapons,
+                                world,
+                                tick,
+                            } => {
+                                // Join may have been resent before the first Welcome arrived
+                                if self.server == Some(pkt.addr()) {
+                                    debug![self.logger, "Ignoring repeated Welcome"];
+                                    continue;
+                                }
                                 info![self.logger, "Received Welcome message!"; "assigned id" => your_id];
+                                match weapons.validate() {
+                                    Ok(()) => self.logic.weapons = weapons,
+                                    Err(err) => {
+                                        error![self.logger, "Server sent invalid weapons"; "reason" => err.to_string()];
+                                    }
+                                }
                                 self.server = Some(pkt.addr());
                                 self.logic.self_id = your_id;
+                                self.logic.delta_ticks.start(tick);
+                                self.start_download(world_width, world_height);
+                                self.enter_world(world);
+                            }
+                            ServerMessage::TerrainChunk { index, data } => {
+                                self.receive_terrain_chunk(index as usize, &data);
+                            }
+                            ServerMessage::TerrainHashes { tick, hashes } => {
+                                self.logic.terrain_hashes = Some((tick, hashes));
+                                self.check_terrain();
                             }
-                            ServerMessage::State { players, bullets } => {
-                                debug![self.logger, "Received state update"; "players" => InDebug(&players), "bullets" => InDebug(&bullets); clone players, bullets];
+                            ServerMessage::NewWorld {
+                                world_width,
+                                world_height,
+                                world_seed,
+                                world,
+                            } => {
+                                info![self.logger, "Received new world"; "seed" => InDebug(&world_seed), "world" => world];
+                                if self.logic.download.is_some() {
+                                    // The server streams the new terrain instead of the old one
+                                    self.start_download(world_width, world_height);
+                                } else {
+                                    self.load_world(world_width, world_height, world_seed);
+                                }
+                                self.enter_world(world);
+                            }
+                            ServerMessage::State {
+                                snapshot,
+                                tick,
+                                baseline,
+                                last_input_sequence,
+                                data,
+                            } => {
+                                let Snapshot { players, bullets } =
+                                    match self.decode_snapshot(snapshot, baseline, &data) {
+                                        Some(decoded) => decoded,
+                                        None => continue,
+                                    };
+                                let server_time = ticks_to_duration(tick);
+                                self.logic.server_clock.observe(server_time, self.time);
+                                if tick < self.logic.removal_tick {
+                                    debug![self.logger, "Ignoring state from before a removal"; "snapshot" => snapshot, "tick" => tick];
+                                    continue;
+                                }
+                                debug![self.logger, "Received state update"; "snapshot" => snapshot, "players" => InDebug(&players), "bullets" => InDebug(&bullets); clone players, bullets];
                                 for player in players {
                                     if self.logic.players.contains_key(&player.id) {
                                         // Update existing player
-                                        if let Some(p) = self.logic.players.get_mut(&player.id) {
-                                            if self.logic.you == player.id {
-                                                if (p.inner.position - player.position)
-                                                    .length_squared()
-                                                    > 10.0
-                                                {
-                                                    p.inner = player;
-                                                }
-                                                continue;
-                                            } else {
-                                                p.inner = player;
-                                            }
+                                        if self.logic.self_id == player.id {
+                                            self.reconcile_own_player(player, last_input_sequence);
+                                        } else if let Some(p) =
+                                            self.logic.players.get_mut(&player.id)
+                                        {
+                                            p.snapshots.push(server_time, player);
                                         }
                                     } else {
                                         // Create new player
                                         let id = player.id;
+                                        let quad = if id == self.logic.self_id {
+                                            None
+                                        } else {
+                                            self.graphics.as_mut().map(|graphics| {
+                                                graphics.windowing.quads().add(
+                                                    &graphics.players_layer,
+                                                    vxdraw::quads::Quad::new()
+                                                        .colors([(0, 0, 255, 255); 4])
+                                                        .width(10.0)
+                                                        .height(10.0)
+                                                        .origin((-5.0, -5.0)),
+                                                )
+                                            })
+                                        };
+                                        let weapon_sprite =
+                                            self.graphics.as_mut().map(|graphics| {
+                                                graphics.windowing.dyntex().add(
+                                                    &graphics.weapons_texture,
+                                                    vxdraw::dyntex::Sprite::new()
+                                                        .width(10.0)
+                                                        .height(5.0),
+                                                )
+                                            });
+                                        let mut snapshots = SnapshotBuffer::default();
+                                        snapshots.push(server_time, player.clone());
                                         let new = ClientPlayer {
+                                            previous_position: player.position,
+                                            correction: Vec2::null_vec(),
                                             inner: player,
                                             input: Input::default(),
-                                            weapon_sprite: None,
+                                            weapon_sprite,
+                                            quad,
+                                            snapshots,
                                         };
                                         self.logic.players.insert(id, new);
                                     }
@@ -262,13 +525,13 @@ impl Client {
                                     if self.logic.bullets.contains_key(&bullet.id) {
                                         // Update existing bullet
                                         if let Some(b) = self.logic.bullets.get_mut(&bullet.id) {
-                                            b.inner = bullet;
+                                            b.snapshots.push(server_time, bullet);
                                         }
                                     } else {
                                         // Create new bullet
 
                                         let handle = if let Some(ref mut graphics) = self.graphics {
-                                            let stats = bullet.get_stats();
+                                            let stats = self.logic.weapons.get(bullet.ty);
                                             Some(
                                                 graphics.windowing.dyntex().add(
                                                     &graphics.bullets_texture,
@@ -289,9 +552,12 @@ impl Client {
                                         };
 
                                         let id = bullet.id;
+                                        let mut snapshots = SnapshotBuffer::default();
+                                        snapshots.push(server_time, bullet.clone());
                                         let new = ClientBullet {
                                             inner: bullet,
-                                            handle: handle.unwrap(), // TODO: make optional here aswell?
+                                            handle,
+                                            snapshots,
 
                                             animation_sequence: 0,
                                             current_uv_begin: (0.0, 0.0),
@@ -304,18 +570,22 @@ impl Client {
                             ServerMessage::DeltaState {
                                 removed,
                                 grid_changes,
+                                world,
+                                tick,
                             } => {
                                 debug![self.logger, "Received deltastate"; "removed" => InDebug(&removed), "grid changes" => InDebug(&grid_changes); clone removed, grid_changes];
-                                // TODO removed
+                                if !removed.is_empty() {
+                                    self.logic.removal_tick = self.logic.removal_tick.max(tick);
+                                }
                                 for (id, ty) in removed {
                                     match ty {
                                         EntityType::Bullet => {
                                             match self.logic.bullets.swap_remove(&id) {
                                                 Some(removed_bullet) => {
-                                                    if let Some(ref mut gfx) = self.graphics {
-                                                        gfx.windowing
-                                                            .dyntex()
-                                                            .remove(removed_bullet.handle);
+                                                    if let (Some(gfx), Some(handle)) =
+                                                        (&mut self.graphics, removed_bullet.handle)
+                                                    {
+                                                        gfx.windowing.dyntex().remove(handle);
                                                     }
                                                 }
                                                 None => {
@@ -323,23 +593,58 @@ impl Client {
                                                 }
                                             }
                                         }
-                                        EntityType::Player => unimplemented!(),
+                                        EntityType::Player => {
+                                            match self.logic.players.swap_remove(&id) {
+                                                Some(removed_player) => {
+                                                    if let Some(ref mut gfx) = self.graphics {
+                                                        if let Some(quad) = removed_player.quad {
+                                                            gfx.windowing.quads().remove(quad);
+                                                        }
+                                                        if let Some(sprite) =
+                                                            removed_player.weapon_sprite
+                                                        {
+                                                            gfx.windowing.dyntex().remove(sprite);
+                                                        }
+                                                    }
+                                                }
+                                                None => {
+                                                    warn![self.logger, "Remove nonexistent player"; "id" => id]
+                                                }
+                                            }
+                                        }
                                     }
                                 }
 
-                                if let Some(ref mut graphics) = self.graphics {
-                                    // info![self.logger, "Received DeltaState"; "changes" => grid_changes.len()];
-                                    graphics.windowing.strtex().set_pixels(
-                                        &graphics.grid,
-                                        grid_changes.iter().map(|pos| {
-                                            (pos.0 as u32, pos.1 as u32, Color::Rgba(0, 0, 0, 255))
-                                        }),
-                                    );
+                                self.receive_grid_changes(world, grid_changes);
+                                self.logic.delta_ticks.insert(tick);
+                                self.check_terrain();
+                            }
+                            ServerMessage::Events(events) => {
+                                for event in events {
+                                    match event {
+                                        GameEvent::Died { id, killer } => {
+                                            info![self.logger, "Player died"; "id" => id, "killer" => InDebug(&killer)];
+                                        }
+                                        GameEvent::Respawned { id, position } => {
+                                            info![self.logger, "Player respawned"; "id" => id, "position" => InDebug(&position)];
+                                        }
+                                    }
                                 }
-                                // TODO update grid
-                                for (x, y, v) in grid_changes {
-                                    self.logic.grid.set(x as usize, y as usize, v);
+                            }
+                            ServerMessage::Round(round) => {
+                                if round.state != self.logic.round.state {
+                                    let (number, state, scores) =
+                                        (round.number, round.state, round.scores);
+                                    info![self.logger, "Round state changed"; "round" => number, "state" => InDebug(&state), "scores" => InDebug(&scores)];
+                                }
+                                self.logic.round = round;
+                            }
+                            ServerMessage::Scoreboard(scoreboard) => {
+                                if scoreboard.state != self.logic.scoreboard.state {
+                                    let state = scoreboard.state;
+                                    info![self.logger, "Match state changed"; "state" => InDebug(&state)];
                                 }
+                                self.logic.scoreboard = scoreboard;
                             }
                         }
                     } else {
@@ -347,113 +652,432 @@ impl Client {
                     }
                 }
                 Some(SocketEvent::Connect(_addr)) => {}
-                Some(SocketEvent::Timeout(_addr)) => {}
+                Some(SocketEvent::Timeout(addr)) => {
+                    if self.server == Some(addr) {
+                        warn![self.logger, "Lost connection to server"; "address" => addr];
+                        self.server = None;
+                    }
+                }
                 None => break,
             }
         }
+        // Acknowledge the newest snapshot, so the server can encode deltas against it
+        if let (Some(addr), Some(snapshot)) = (self.server, self.logic.unacked_snapshot.take()) {
+            self.network
+                .send(Packet::unreliable(
+                    addr,
+                    ClientMessage::AckSnapshot(snapshot).serialize(),
+                ))
+                .unwrap();
+        }
+        // Tell the server what we are looking at, so it only sends what is nearby
+        if let (Some(addr), Some(_)) = (self.server, &self.graphics) {
+            self.network
+                .send(Packet::unreliable_sequenced(
+                    addr,
+                    ClientMessage::View(self.logic.cam).serialize(),
+                    None,
+                ))
+                .unwrap();
+        }
         // Send input to server
+        if let Some(addr) = self.server {
+            let sequence = self.logic.input_sequence.wrapping_add(1);
+            self.logic.input_sequence = sequence;
+            let aim = self.collect_aim();
+            if let (Some(aim), Some(player)) =
+                (aim, self.logic.players.get_mut(&self.logic.self_id))
+            {
+                player.aim = aim;
+            }
+            self.network
+                .send(Packet::reliable_ordered(
+                    addr,
+                    ClientMessage::Input {
+                        sequence,
+                        commands,
+                        aim,
+                    }
+                    .serialize(),
+                    None,
+                ))
+                .unwrap();
+            self.logic
+                .pending_inputs
+                .push_back((sequence, self.actions));
+            if self.logic.pending_inputs.len() > MAX_PENDING_INPUTS {
+                self.logic.pending_inputs.pop_front();
+            }
+        }
+    }
+
+    /// Switches to world generation `world` and applies the grid changes of it that arrived
+    /// before we knew about it
+    fn enter_world(&mut self, world: u32) {
+        self.logic.world = world;
+        let early = std::mem::replace(&mut self.logic.early_grid_changes, Vec::new());
+        for (world, grid_changes) in early {
+            self.receive_grid_changes(world, grid_changes);
+        }
+    }
+
+    /// Applies grid changes of world generation `world`, or holds them until the terrain
+    /// download or the `NewWorld` of their world is complete. Changes of a previous world are
+    /// dropped.
+    fn receive_grid_changes(&mut self, world: u32, grid_changes: Vec<GridRect>) {
+        if world != self.logic.world {
+            if world > self.logic.world {
+                self.logic.early_grid_changes.push((world, grid_changes));
+            } else {
+                debug![self.logger, "Dropping grid changes of a previous world"; "world" => world];
+            }
+            return;
+        }
+        match self.logic.download {
+            Some(ref mut download) => download.grid_changes.extend(grid_changes),
+            None => self.apply_grid_changes(&grid_changes),
+        }
+    }
+
+    /// Applies grid changes from the server to our grid and the terrain texture, uploading all
+    /// changed pixels in a single batch
+    fn apply_grid_changes(&mut self, rects: &[GridRect]) {
+        let (width, height) = self.logic.grid.get_size();
+        let cells: Vec<(u32, u32, Reality)> = decode_grid_changes(rects, width, height).collect();
+        for (x, y, value) in &cells {
+            self.logic.grid.set(*x as usize, *y as usize, *value);
+        }
+        if let Some(ref mut graphics) = self.graphics {
+            if graphics.grid_sprite.is_some() {
+                graphics.windowing.strtex().set_pixels(
+                    &graphics.grid,
+                    cells.iter().map(|(x, y, v)| (*x, *y, cell_color(*v))),
+                );
+            }
+        }
+    }
+
+    /// Generates the world the server told us about and shows it
+    fn load_world(&mut self, width: usize, height: usize, seed: [f32; 3]) {
+        self.logic.grid = generate_world(width, height, seed);
+        self.locate_bases(width, height);
+        if let Some(ref mut graphics) = self.graphics {
+            upload_grid(graphics, &self.logic.grid);
+        }
+    }
+
+    /// Starts receiving the terrain of a world of the given size from the server
+    fn start_download(&mut self, width: usize, height: usize) {
+        self.logic.download = Some(TerrainDownload::new(width, height));
+        self.locate_bases(width, height);
+        self.show_download_progress();
+        // An empty world has no chunks to wait for
+        if self
+            .logic
+            .download
+            .as_ref()
+            .map_or(false, |x| x.is_complete())
+        {
+            self.finish_download();
+        }
+    }
+
+    /// Adds a chunk to the terrain download, reporting progress every tenth of the terrain
+    fn receive_terrain_chunk(&mut self, index: usize, data: &[u8]) {
+        let download = match self.logic.download {
+            Some(ref mut download) => download,
+            // A chunk we asked for again because it differed from the server's
+            None => {
+                self.replace_terrain_chunk(index, data);
+                return;
+            }
+        };
+        let before = download.progress();
+        if let Err(err) = download.insert(index, data) {
+            warn![self.logger, "Unable to decode terrain chunk"; "index" => index, "reason" => err.to_string()];
+            return;
+        }
+        let progress = download.progress();
+        if (progress * 10.0).floor() > (before * 10.0).floor() {
+            let percent = (progress * 100.0).round() as u32;
+            info![self.logger, "Receiving terrain"; "percent" => percent];
+        }
+        if download.is_complete() {
+            self.finish_download();
+        } else if (progress * 100.0).floor() > (before * 100.0).floor() {
+            self.show_download_progress();
+        }
+    }
+
+    /// Shows how much of the terrain has arrived in the middle of the screen, or removes the
+    /// text when there is no download
+    fn show_download_progress(&mut self) {
+        let graphics = match self.graphics {
+            Some(ref mut graphics) => graphics,
+            None => return,
+        };
+        if let Some(text) = graphics.download_text.take() {
+            graphics.windowing.text().remove(text);
+        }
+        if let Some(ref download) = self.logic.download {
+            let percent = (download.progress() * 100.0).floor() as u32;
+            let text = graphics.windowing.text().add(
+                &graphics.text_layer,
+                &format!["Receiving terrain {}%", percent],
+                vxdraw::text::TextOptions::new()
+                    .font_size(40.0)
+                    .translation(self.logic.cam.center.into()),
+            );
+            graphics.download_text = Some(text);
+        }
+    }
+
+    /// Switches to the downloaded terrain, shows it and applies the grid changes that arrived
+    /// during the download
+    fn finish_download(&mut self) {
+        if let Some(download) = self.logic.download.take() {
+            self.logic.grid = download.grid;
+            if let Some(ref mut graphics) = self.graphics {
+                upload_grid(graphics, &self.logic.grid);
+            }
+            self.apply_grid_changes(&download.grid_changes);
+            self.show_download_progress();
+            info![self.logger, "Terrain complete, starting simulation"];
+        }
+    }
+
+    /// Replaces chunk `index` of our terrain and shows it
+    fn replace_terrain_chunk(&mut self, index: usize, data: &[u8]) {
+        if let Err(err) = decode_chunk(&mut self.logic.grid, index, data) {
+            warn![self.logger, "Unable to decode terrain chunk"; "index" => index, "reason" => err.to_string()];
+            return;
+        }
+        debug![self.logger, "Replaced terrain chunk"; "index" => index];
+        let grid = &self.logic.grid;
+        let (width, height) = grid.get_size();
+        if let Some(ref mut graphics) = self.graphics {
+            if graphics.grid_sprite.is_some() {
+                graphics.windowing.strtex().set_pixels(
+                    &graphics.grid,
+                    chunk_cells(index, width, height)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|(x, y)| {
+                            grid.get(x, y)
+                                .map(|value| (x as u32, y as u32, cell_color(*value)))
+                        }),
+                );
+            }
+        }
+    }
+
+    /// Compares our terrain with the latest chunk hashes of the server and asks for the chunks
+    /// that differ. Waits until we have applied the grid changes the hashes cover.
+    fn check_terrain(&mut self) {
+        if self.logic.download.is_some() {
+            return;
+        }
+        let hashes = match self.logic.terrain_hashes.take() {
+            Some((tick, hashes)) if self.logic.delta_ticks.has_all(tick) => hashes,
+            waiting => {
+                self.logic.terrain_hashes = waiting;
+                return;
+            }
+        };
+        let mismatches = mismatching_chunks(&self.logic.grid, &hashes);
+        if mismatches.is_empty() {
+            return;
+        }
+        self.logger.warn(Log::TerrainMismatch(mismatches.clone()));
+        if let Some(addr) = self.server {
+            let chunks = mismatches.iter().map(|index| *index as u32).collect();
+            self.network
+                .send(Packet::reliable_ordered(
+                    addr,
+                    ClientMessage::RequestTerrainChunks(chunks).serialize(),
+                    None,
+                ))
+                .unwrap();
+        }
+    }
+
+    fn locate_bases(&mut self, width: usize, height: usize) {
+        let [black, white] = Base::for_world(width, height);
+        self.logic.black_base = black.position;
+        self.logic.white_base = white.position;
+    }
+
+    /// Decodes a snapshot and stores it as a possible baseline for later snapshots.
+    ///
+    /// Returns `None` if the baseline the server encoded against is no longer known or the data
+    /// is corrupt. The server then keeps encoding against an older acknowledged snapshot or
+    /// eventually sends a full state.
+    fn decode_snapshot(
+        &mut self,
+        snapshot: u32,
+        baseline: Option<u32>,
+        data: &[u8],
+    ) -> Option<Snapshot> {
+        let received = &mut self.logic.received_snapshots;
+        if received
+            .back()
+            .map_or(false, |(newest, _)| *newest >= snapshot)
+        {
+            debug![self.logger, "Ignoring outdated snapshot"; "snapshot" => snapshot];
+            return None;
+        }
+        let base = match baseline {
+            Some(baseline) => match received.iter().find(|(number, _)| *number == baseline) {
+                Some((_, base)) => Some(base),
+                None => {
+                    warn![self.logger, "Snapshot baseline is unknown"; "snapshot" => snapshot, "baseline" => baseline];
+                    return None;
+                }
+            },
+            None => None,
+        };
+        let decoded = match decode_snapshot(data, base) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                warn![self.logger, "Unable to decode snapshot"; "snapshot" => snapshot, "reason" => err.to_string()];
+                return None;
+            }
+        };
+
+        // Older snapshots will never be used as a baseline again once this one is acknowledged
+        if let Some(baseline) = baseline {
+            while let Some((oldest, _)) = received.front() {
+                if *oldest < baseline {
+                    received.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+        received.push_back((snapshot, decoded.clone()));
+        if received.len() > MAX_RECEIVED_SNAPSHOTS {
+            received.pop_front();
+        }
+        self.logic.unacked_snapshot = Some(snapshot);
+        Some(decoded)
+    }
+
+    /// Replaces our own player with the authoritative state from the server, then replays the
+    /// inputs the server has not processed yet on top of it.
+    fn reconcile_own_player(&mut self, authoritative: PlayerData, last_input_sequence: u32) {
+        let pending = &mut self.logic.pending_inputs;
+        while let Some((sequence, _)) = pending.front() {
+            if *sequence <= last_input_sequence {
+                pending.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let mut predicted = authoritative;
+        // Without terrain there is nothing to predict against
+        let replayed = if self.logic.download.is_none() {
+            pending.len()
+        } else {
+            0
+        };
+        for (_, actions) in pending.iter().take(replayed) {
+            update_player(
+                &mut predicted,
+                actions,
+                &self.logic.config,
+                &mut self.random,
+                &self.logic.grid,
+                &mut self.logger,
+            );
+        }
+        if let Some(player) = self.logic.players.get_mut(&self.logic.self_id) {
+            // Keep drawing the player where it was and move it over to the correction gradually
+            let correction = player.correction + player.position - predicted.position;
+            player.correction = if correction.length() > MAX_SMOOTHED_CORRECTION {
+                Vec2::null_vec()
+            } else {
+                correction
+            };
+            player.inner = predicted;
+        }
+    }
+
+    /// Maps the current input to actions and returns how they changed since the last tick
+    fn collect_input(&mut self) -> Vec<InputCommand> {
+        let actions = self.controls.actions(&self.input);
+        let commands = self.actions.changes_to(&actions);
+        self.actions = actions;
+        commands
+    }
+
+    /// Asks the server to let our player hold `weapon`
+    pub fn switch_weapon(&mut self, weapon: Weapon) {
+        self.logic.weapon = weapon;
+        if let Some(addr) = self.server {
+            self.network
+                .send(Packet::reliable_ordered(
+                    addr,
+                    ClientMessage::SwitchWeapon(weapon).serialize(),
+                    None,
+                ))
+                .unwrap();
+        }
+    }
+
+    /// Asks the server for the current scoreboard, it arrives in `ClientLogic::scoreboard`
+    pub fn request_scoreboard(&mut self) {
         if let Some(addr) = self.server {
             self.network
                 .send(Packet::reliable_ordered(
                     addr,
-                    self.collect_input().serialize(),
+                    ClientMessage::RequestScoreboard.serialize(),
                     None,
                 ))
                 .unwrap();
         }
     }
 
-    fn collect_input(&self) -> ClientMessage {
-        let mut commands = Vec::new();
-        if self.input.is_key_toggled_down(Key::Down) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Pressed,
-                virtual_keycode: VirtualKeyCode::Down,
-                modifiers: self.input.key_modifiers_state(Key::Down),
-            });
-        } else if self.input.is_key_toggled_up(Key::Down) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Released,
-                virtual_keycode: VirtualKeyCode::Down,
-                modifiers: self.input.key_modifiers_state(Key::Down),
-            });
-        }
-        if self.input.is_key_toggled_down(Key::Up) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Pressed,
-                virtual_keycode: VirtualKeyCode::Up,
-                modifiers: self.input.key_modifiers_state(Key::Up),
-            });
-        } else if self.input.is_key_toggled_up(Key::Up) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Released,
-                virtual_keycode: VirtualKeyCode::Up,
-                modifiers: self.input.key_modifiers_state(Key::Up),
-            });
-        }
-        if self.input.is_key_toggled_down(Key::Left) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Pressed,
-                virtual_keycode: VirtualKeyCode::Left,
-                modifiers: self.input.key_modifiers_state(Key::Left),
-            });
-        } else if self.input.is_key_toggled_up(Key::Left) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Released,
-                virtual_keycode: VirtualKeyCode::Left,
-                modifiers: self.input.key_modifiers_state(Key::Left),
-            });
-        }
-        if self.input.is_key_toggled_down(Key::Right) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Pressed,
-                virtual_keycode: VirtualKeyCode::Right,
-                modifiers: self.input.key_modifiers_state(Key::Right),
-            });
-        } else if self.input.is_key_toggled_up(Key::Right) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Released,
-                virtual_keycode: VirtualKeyCode::Right,
-                modifiers: self.input.key_modifiers_state(Key::Right),
-            });
-        }
-        if self.input.is_key_toggled_down(Key::LShift) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Pressed,
-                virtual_keycode: VirtualKeyCode::LShift,
-                modifiers: self.input.key_modifiers_state(Key::LShift),
-            });
-        } else if self.input.is_key_toggled_up(Key::LShift) {
-            commands.push(InputCommand::Keyboard {
-                state: ElementState::Released,
-                virtual_keycode: VirtualKeyCode::LShift,
-                modifiers: self.input.key_modifiers_state(Key::LShift),
-            });
-        }
-        if self.input.is_mouse_button_toggled_down(MouseButton::Left) {
-            commands.push(InputCommand::Mouse {
-                position: self.input.get_mouse_position(),
-                state: ElementState::Pressed,
-                button: MouseButton::Left,
-                modifiers: self.input.mouse_button_modifiers_state(MouseButton::Left),
-            });
-        } else if self.input.is_mouse_button_toggled_up(MouseButton::Left) {
-            commands.push(InputCommand::Mouse {
-                position: self.input.get_mouse_position(),
-                state: ElementState::Released,
-                button: MouseButton::Left,
-                modifiers: self.input.mouse_button_modifiers_state(MouseButton::Left),
-            });
-        }
-
-        let mouse_pos = match self.graphics {
-            Some(ref graphics) => graphics
-                .windowing
-                .to_world_coords(self.input.get_mouse_position()),
-            None => (0.0, 0.0),
+    /// The weapon selected this tick with a number key, the mouse wheel or
+    /// `Action::SwitchWeapon`, if it differs from the current one
+    fn collect_weapon_switch(&self, commands: &[InputCommand]) -> Option<Weapon> {
+        let weapons = &self.logic.weapons;
+        let current = self.logic.weapon;
+        let number = WEAPON_KEYS
+            .iter()
+            .take(weapons.len())
+            .position(|key| self.input.is_key_toggled_down(*key))
+            .map(|index| Weapon(index as u8));
+        let wheel = self.input.get_mouse_wheel();
+        let weapon = if let Some(weapon) = number {
+            weapon
+        } else if commands.contains(&InputCommand::Pressed(Action::SwitchWeapon)) {
+            weapons.next(current)
+        } else if self.input.is_key_down(Key::LControl) {
+            // The wheel zooms the camera while control is held
+            current
+        } else if wheel > 0.0 {
+            weapons.next(current)
+        } else if wheel < 0.0 {
+            weapons.previous(current)
+        } else {
+            current
         };
+        if weapon != current {
+            Some(weapon)
+        } else {
+            None
+        }
+    }
 
-        ClientMessage::Input(commands)
+    /// World-space angle from our own player to the cursor, `None` without graphics
+    fn collect_aim(&self) -> Option<f32> {
+        let graphics = self.graphics.as_ref()?;
+        let player = self.logic.players.get(&self.logic.self_id)?;
+        let mouse_in_world = graphics
+            .windowing
+            .to_world_coords(self.input.get_mouse_position());
+        Some((Vec2::from(mouse_in_world) - player.position - PLAYER_CENTER).angle())
     }
 
     fn maybe_initialize_graphics(&mut self) {
@@ -475,32 +1099,10 @@ impl Client {
             windowing.dyntex().add(&background, dyntex::Sprite::new());
         }
 
-        let mut strtex = windowing.strtex();
+        let grid = windowing
+            .strtex()
+            .add_layer(&terrain_layer_options(WORLD_WIDTH, WORLD_HEIGHT));
 
-        let tex = strtex.add_layer(
-            &strtex::LayerOptions::new()
-                .width(1000)
-                .height(1000)
-                .depth(false),
-        );
-        self.logic.grid.resize(1000, 1000);
-
-        strtex.fill_with_perlin_noise(&tex, [0.0, 0.0, 0.0]); // TODO should get seed from server and wait with creating the world.
-        let grid = &mut self.logic.grid;
-        strtex.read(&tex, |x, pitch| {
-            for j in 0..1000 {
-                for i in 0..1000 {
-                    grid.set(i, j, x[i + j * pitch].0);
-                }
-            }
-        });
-        strtex.add(
-            &tex,
-            vxdraw::strtex::Sprite::new()
-                .width(1000.0)
-                .height(1000.0)
-                .translation((500.0, 500.0)),
-        );
         let layer = windowing
             .quads()
             .add_layer(&vxdraw::quads::LayerOptions::default());
@@ -533,15 +1135,69 @@ impl Client {
 
         self.graphics = Some(Graphics {
             basic_text,
+            text_layer,
+            download_text: None,
             player_quads: vec![handle],
+            players_layer: layer,
             bullets_texture: fireballs,
-            grid: tex,
+            grid,
+            grid_size: (WORLD_WIDTH, WORLD_HEIGHT),
+            grid_sprite: None,
             weapons_texture,
             windowing,
         });
     }
 }
 
+/// Colour of a terrain cell as drawn on the grid layer
+fn cell_color(value: Reality) -> Color {
+    if value > 0 {
+        Color::Rgba(value, value, value, 255)
+    } else {
+        Color::Rgba(0, 0, 0, 255)
+    }
+}
+
+fn terrain_layer_options(width: usize, height: usize) -> strtex::LayerOptions {
+    strtex::LayerOptions::new()
+        .width(width)
+        .height(height)
+        .depth(false)
+}
+
+/// Uploads every cell of `grid` to the terrain layer and shows it. The layer is reused for worlds
+/// of its size, a world of another size replaces it with a new layer, which vxdraw then draws
+/// above the layers created after the original one.
+fn upload_grid(graphics: &mut Graphics, grid: &Grid<Reality>) {
+    let (width, height) = grid.get_size();
+    let mut strtex = graphics.windowing.strtex();
+    if graphics.grid_size != (width, height) {
+        if let Some(sprite) = graphics.grid_sprite.take() {
+            strtex.remove(sprite);
+        }
+        let layer = strtex.add_layer(&terrain_layer_options(width, height));
+        strtex.remove_layer(std::mem::replace(&mut graphics.grid, layer));
+        graphics.grid_size = (width, height);
+    }
+    strtex.set_pixels(
+        &graphics.grid,
+        (0..height).flat_map(|j| {
+            (0..width).map(move |i| (i as u32, j as u32, cell_color(*grid.get(i, j).unwrap())))
+        }),
+    );
+    if graphics.grid_sprite.is_none() {
+        graphics.grid_sprite = Some(
+            strtex.add(
+                &graphics.grid,
+                vxdraw::strtex::Sprite::new()
+                    .width(width as f32)
+                    .height(height as f32)
+                    .translation((width as f32 / 2.0, height as f32 / 2.0)),
+            ),
+        );
+    }
+}
+
 pub fn process_input(s: &mut Input, events: &mut winit::EventsLoop) {
     s.prepare_for_next_frame();
     events.poll_events(|evt| {
@@ -576,6 +1232,35 @@ pub fn process_input(s: &mut Input, events: &mut winit::EventsLoop) {
     });
 }
 
+/// Moves remote players and bullets to their interpolated state `delay` before the estimated
+/// server time
+fn interpolate_remote_entities(
+    s: &mut ClientLogic,
+    now: Instant,
+    delay: Duration,
+    max_extrapolation: Duration,
+) {
+    let render_time = match s.server_clock.server_time(now) {
+        Some(server_time) => server_time
+            .checked_sub(delay)
+            .unwrap_or_else(|| Duration::new(0, 0)),
+        None => return,
+    };
+    for (id, player) in s.players.iter_mut() {
+        if *id == s.self_id {
+            continue;
+        }
+        if let Some(state) = player.snapshots.sample(render_time, max_extrapolation) {
+            player.inner = state;
+        }
+    }
+    for bullet in s.bullets.values_mut() {
+        if let Some(state) = bullet.snapshots.sample(render_time, max_extrapolation) {
+            bullet.inner = state;
+        }
+    }
+}
+
 fn move_camera_according_to_input(s: &mut Client) {
     if s.input.is_key_down(Key::D) {
         s.logic.cam.center.x += 5.0;
@@ -613,6 +1298,18 @@ fn move_camera_according_to_input(s: &mut Client) {
     }
 }
 
+/// Sets the size of the camera to the part of the world that is visible in the window
+fn update_view_size(s: &mut Client) {
+    if let Some(ref graphics) = s.graphics {
+        let (width, height) = graphics.windowing.get_window_size_in_pixels_float();
+        let top_left = Vec2::from(graphics.windowing.to_world_coords((0.0, 0.0)));
+        let bottom_right = Vec2::from(graphics.windowing.to_world_coords((width, height)));
+        let size = bottom_right - top_left;
+        s.logic.cam.width = size.x.abs().ceil() as u32;
+        s.logic.cam.height = size.y.abs().ceil() as u32;
+    }
+}
+
 fn set_gravity(s: &mut Client) {
     if s.input.is_key_toggled_down(Key::G) {
         s.logic.config.gravity_on = !s.logic.config.gravity_on;
@@ -629,51 +1326,90 @@ fn toggle_camera_mode(s: &mut Client) {
 }
 fn update_graphics(s: &mut Client) {
     if let Some(ref mut graphics) = s.graphics {
-        graphics.windowing.dyntex().set_uvs(
-            s.logic
-                .bullets
-                .values()
-                .map(|b| (&b.handle, b.current_uv_begin, b.current_uv_end)),
-        );
+        graphics
+            .windowing
+            .dyntex()
+            .set_uvs(s.logic.bullets.values().filter_map(|b| {
+                b.handle
+                    .as_ref()
+                    .map(|handle| (handle, b.current_uv_begin, b.current_uv_end))
+            }));
 
         for b in s.logic.bullets.values() {
-            graphics
-                .windowing
-                .dyntex()
-                .set_translation(&b.handle, b.position.into());
+            if let Some(ref handle) = b.handle {
+                graphics
+                    .windowing
+                    .dyntex()
+                    .set_translation(handle, b.position.into());
+            }
         }
 
         {
             if let Some(player) = s.logic.players.get_mut(&s.logic.self_id) {
+                // Follow the cursor every frame rather than waiting for the next tick
                 let mouse_in_world = graphics
                     .windowing
                     .to_world_coords(s.input.get_mouse_position());
-                let angle = -(Vec2::from(mouse_in_world) - player.position - PLAYER_CENTER).angle();
-
-                if let Some(ref mut sprite) = player.weapon_sprite {
-                    if angle > std::f32::consts::PI / 2.0 || angle < -std::f32::consts::PI / 2.0 {
-                        graphics
-                            .windowing
-                            .dyntex()
-                            .set_uv(sprite, (0.0, 1.0), (1.0, 0.0));
-                    } else {
-                        graphics
-                            .windowing
-                            .dyntex()
-                            .set_uv(sprite, (0.0, 0.0), (1.0, 1.0));
-                    }
-                    graphics.windowing.dyntex().set_rotation(sprite, Rad(angle));
+                let aim = (Vec2::from(mouse_in_world) - player.position - PLAYER_CENTER).angle();
+
+                if let Some(ref sprite) = player.weapon_sprite {
+                    let stats = s.logic.weapons.get(s.logic.weapon);
+                    orient_weapon_sprite(&mut graphics.windowing, sprite, aim, stats);
                 }
             }
         }
 
+        for (id, player) in s.logic.players.iter() {
+            if *id == s.logic.self_id {
+                continue;
+            }
+            if let Some(ref quad) = player.quad {
+                graphics
+                    .windowing
+                    .quads()
+                    .set_translation(quad, player.position.into());
+            }
+            if let Some(ref sprite) = player.weapon_sprite {
+                graphics
+                    .windowing
+                    .dyntex()
+                    .set_translation(sprite, (player.position + PLAYER_CENTER).into());
+                orient_weapon_sprite(
+                    &mut graphics.windowing,
+                    sprite,
+                    player.aim,
+                    s.logic.weapons.get(player.curr_weapon),
+                );
+            }
+        }
+
         upload_player_position(
             &mut s.logic,
             &mut graphics.windowing,
             &graphics.player_quads[0],
+            s.timestep.alpha(),
         );
     }
 }
+/// Shows the weapon described by `stats` on a weapon sprite and points it along the world-space angle `aim`, flipping it
+/// when aiming to the left so it is never drawn upside down
+fn orient_weapon_sprite(
+    windowing: &mut VxDraw,
+    sprite: &vxdraw::dyntex::Handle,
+    aim: f32,
+    stats: &WeaponStats,
+) {
+    let (begin, end) = (stats.weapon_uv_begin, stats.weapon_uv_end);
+    let angle = -aim;
+    if angle > std::f32::consts::PI / 2.0 || angle < -std::f32::consts::PI / 2.0 {
+        windowing
+            .dyntex()
+            .set_uv(sprite, (begin.0, end.1), (end.0, begin.1));
+    } else {
+        windowing.dyntex().set_uv(sprite, begin, end);
+    }
+    windowing.dyntex().set_rotation(sprite, Rad(angle));
+}
 fn draw_graphics(s: &mut Client) {
     if let Some(ref mut graphics) = s.graphics {
         let persp = graphics.windowing.perspective_projection();
@@ -686,28 +1422,32 @@ fn draw_graphics(s: &mut Client) {
         graphics.windowing.draw_frame();
     }
 }
+/// Draws our own player `alpha` of the way from its previous towards its current tick position,
+/// offset by what is left of the last correction
 fn upload_player_position(
     s: &mut ClientLogic,
     windowing: &mut VxDraw,
     handle: &vxdraw::quads::Handle,
+    alpha: f32,
 ) {
     if let Some(ref mut player) = s.players.get(&s.self_id) {
+        let position = player.previous_position
+            + (player.position - player.previous_position).scale_uni(alpha)
+            + player.correction;
         if let Some(ref gun_handle) = player.weapon_sprite {
             windowing
                 .dyntex()
-                .set_translation(gun_handle, (player.position + PLAYER_CENTER).into());
+                .set_translation(gun_handle, (position + PLAYER_CENTER).into());
         }
         windowing
             .quads()
             .set_solid_color(handle, Color::Rgba(0, 255, 0, 255));
-        windowing
-            .quads()
-            .set_translation(handle, player.position.into());
+        windowing.quads().set_translation(handle, position.into());
     }
 }
 fn update_bullets_uv(s: &mut ClientLogic) {
     for b in s.bullets.values_mut() {
-        let stats = b.get_stats();
+        let stats = s.weapons.get(b.ty);
         let width_elem = b.animation_sequence % stats.width;
         let height_elem = b.animation_sequence / stats.width;
         let uv_begin = (
@@ -730,32 +1470,3 @@ fn update_bullets_uv(s: &mut ClientLogic) {
         b.current_uv_end = current_uv_end;
     }
 }
-fn handle_mouse_scroll(_s: &mut Client) {
-    // TODO
-    /*
-    let wheel = s.logic.input.get_mouse_wheel();
-    match wheel {
-        x if x == 0.0 => {}
-        x if x > 0.0 => {
-            s.logic.current_weapon = Weapon::Ak47;
-            if let Some(this_player) = s.logic.players.get_mut(0) {
-                if let Some(ref mut gfx) = s.graphics {
-                    let new = gfx.windowing.dyntex().add(
-                        &gfx.weapons_texture,
-                        dyntex::Sprite::new().width(10.0).height(5.0),
-                    );
-                    let old = std::mem::replace(&mut this_player.weapon_sprite, Some(new));
-                    if let Some(old_id) = old {
-                        gfx.windowing.dyntex().remove(old_id);
-                    }
-                }
-            }
-        }
-        x if x < 0.0 => {
-            // s.logic.current_weapon = Weapon::Hellfire;
-            // TODO: Switch weapon
-        }
-        _ => {}
-    }
-    */
-}
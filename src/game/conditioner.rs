@@ -0,0 +1,209 @@
+use laminar::{DeliveryGuarantee, Packet, Socket, SocketEvent};
+use rand::Rng;
+use rand_pcg::Pcg64Mcg;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Network conditions simulated for outgoing packets. The default is a perfect network.
+///
+/// Loss and duplication only affect unreliable packets. Laminar resends lost reliable packets,
+/// so for those the conditions only show up as latency.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkConditions {
+    pub latency: Duration,
+    /// Maximum extra delay added on top of `latency`, picked uniformly per packet. Packets
+    /// overtaking each other is the result of jitter.
+    pub jitter: Duration,
+    /// Probability in [0, 1] that an unreliable packet is dropped
+    pub loss: f32,
+    /// Probability in [0, 1] that an unreliable packet is sent twice
+    pub duplication: f32,
+}
+
+impl NetworkConditions {
+    fn is_perfect(&self) -> bool {
+        self.latency == Duration::new(0, 0)
+            && self.jitter == Duration::new(0, 0)
+            && self.loss <= 0.0
+            && self.duplication <= 0.0
+    }
+
+    /// Instants at which copies of a packet sent at `now` are handed to the socket.
+    /// Empty if the packet is lost.
+    fn release_times(&self, random: &mut Pcg64Mcg, now: Instant, reliable: bool) -> Vec<Instant> {
+        if reliable {
+            return vec![now + self.latency];
+        }
+        if random.gen::<f32>() < self.loss {
+            return vec![];
+        }
+        let copies = if random.gen::<f32>() < self.duplication {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| now + self.latency + self.jitter.mul_f32(random.gen::<f32>()))
+            .collect()
+    }
+}
+
+/// A laminar socket that holds back, drops and duplicates outgoing packets according to
+/// `NetworkConditions`. Give both ends conditions to affect traffic in both directions.
+pub struct ConditionedSocket {
+    socket: Socket,
+    conditions: NetworkConditions,
+    random: Pcg64Mcg,
+    /// Packets waiting to be sent, with the instant they are due
+    delayed: Vec<(Instant, Packet)>,
+    /// Time of the latest poll, delays are counted from here
+    now: Instant,
+}
+
+impl ConditionedSocket {
+    /// Wraps `socket` without changing its behaviour
+    pub fn new(socket: Socket) -> ConditionedSocket {
+        ConditionedSocket {
+            socket,
+            conditions: NetworkConditions::default(),
+            random: Pcg64Mcg::new(0),
+            delayed: Vec::new(),
+            now: Instant::now(),
+        }
+    }
+
+    /// Simulate `conditions` from now on, with random decisions drawn from `seed`
+    pub fn set_conditions(&mut self, conditions: NetworkConditions, seed: u128) {
+        self.conditions = conditions;
+        self.random = Pcg64Mcg::new(seed);
+    }
+
+    pub fn send(&mut self, packet: Packet) -> laminar::Result<()> {
+        if self.conditions.is_perfect() && self.delayed.is_empty() {
+            return self.socket.send(packet);
+        }
+        let reliable = match packet.delivery_guarantee() {
+            DeliveryGuarantee::Reliable => true,
+            DeliveryGuarantee::Unreliable => false,
+        };
+        for due in self
+            .conditions
+            .release_times(&mut self.random, self.now, reliable)
+        {
+            self.delayed.push((due, packet.clone()));
+        }
+        Ok(())
+    }
+
+    pub fn recv(&mut self) -> Option<SocketEvent> {
+        self.socket.recv()
+    }
+
+    /// Hands every packet that is due to the socket, then polls the socket itself
+    pub fn manual_poll(&mut self, now: Instant) {
+        self.now = now;
+        if !self.delayed.is_empty() {
+            self.delayed.sort_by_key(|(due, _)| *due);
+            let due = self
+                .delayed
+                .iter()
+                .position(|(due, _)| *due > now)
+                .unwrap_or(self.delayed.len());
+            for (_, packet) in self.delayed.drain(..due) {
+                let _ = self.socket.send(packet);
+            }
+        }
+        self.socket.manual_poll(now);
+    }
+
+    pub fn local_addr(&self) -> laminar::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn perfect_network_sends_once_immediately() {
+        let now = Instant::now();
+        let mut random = Pcg64Mcg::new(0);
+        let conditions = NetworkConditions::default();
+        assert![conditions.is_perfect()];
+        assert_eq![vec![now], conditions.release_times(&mut random, now, false)];
+    }
+
+    #[test]
+    fn delay_stays_within_jitter() {
+        let now = Instant::now();
+        let mut random = Pcg64Mcg::new(0);
+        let conditions = NetworkConditions {
+            latency: ms(50),
+            jitter: ms(20),
+            ..NetworkConditions::default()
+        };
+        for _ in 0..100 {
+            for due in conditions.release_times(&mut random, now, false) {
+                assert![due >= now + ms(50)];
+                assert![due <= now + ms(70)];
+            }
+        }
+    }
+
+    #[test]
+    fn loss_and_duplication_follow_probabilities() {
+        let now = Instant::now();
+        let mut random = Pcg64Mcg::new(1);
+        let conditions = NetworkConditions {
+            loss: 0.25,
+            duplication: 0.5,
+            ..NetworkConditions::default()
+        };
+        let copies: Vec<usize> = (0..10_000)
+            .map(|_| conditions.release_times(&mut random, now, false).len())
+            .collect();
+        let lost = copies.iter().filter(|x| **x == 0).count();
+        let duplicated = copies.iter().filter(|x| **x == 2).count();
+        assert![(2_000..3_000).contains(&lost)];
+        assert![(3_250..4_250).contains(&duplicated)];
+    }
+
+    #[test]
+    fn reliable_packets_are_only_delayed() {
+        let now = Instant::now();
+        let mut random = Pcg64Mcg::new(0);
+        let conditions = NetworkConditions {
+            latency: ms(10),
+            jitter: ms(100),
+            loss: 1.0,
+            duplication: 1.0,
+        };
+        assert_eq![
+            vec![now + ms(10)],
+            conditions.release_times(&mut random, now, true)
+        ];
+    }
+
+    #[test]
+    fn same_seed_gives_same_decisions() {
+        let now = Instant::now();
+        let conditions = NetworkConditions {
+            latency: ms(10),
+            jitter: ms(10),
+            loss: 0.3,
+            duplication: 0.3,
+        };
+        let decisions = |seed| {
+            let mut random = Pcg64Mcg::new(seed);
+            (0..100)
+                .map(|_| conditions.release_times(&mut random, now, false))
+                .collect::<Vec<_>>()
+        };
+        assert_eq![decisions(7), decisions(7)];
+    }
+}
@@ -0,0 +1,175 @@
+use crate::game::Reality;
+use geometry::grid2d::Grid;
+
+/// Shape of the hole a bullet blows into the terrain
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum CraterShape {
+    /// Cells within the radius of the impact
+    Circle,
+    /// Cells within the radius of the impact along both axes
+    Square,
+    /// Offsets from the impact cell, the radius is ignored
+    Custom(Vec<(i32, i32)>),
+}
+
+impl Default for CraterShape {
+    fn default() -> CraterShape {
+        CraterShape::Circle
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crater {
+    pub shape: CraterShape,
+    pub radius: i32,
+    /// Fraction of the crater, measured inwards from its rim, where terrain is broken into
+    /// debris instead of being cleared. 0 clears the whole crater.
+    pub falloff: f32,
+}
+
+impl Crater {
+    /// Offsets from the impact covered by the crater, each with its distance from the impact
+    /// relative to the size of the crater, from 0 at the impact to 1 at the rim
+    fn stamp(&self) -> Vec<((i32, i32), f32)> {
+        let r = self.radius.max(0);
+        let relative = |distance: f32, size: f32| {
+            if size > 0.0 {
+                distance / size
+            } else {
+                0.0
+            }
+        };
+        let square = (-r..=r).flat_map(|i| (-r..=r).map(move |j| (i, j)));
+        match &self.shape {
+            CraterShape::Circle => square
+                .filter(|(i, j)| i * i + j * j <= r * r)
+                .map(|(i, j)| {
+                    let distance = ((i * i + j * j) as f32).sqrt();
+                    ((i, j), relative(distance, r as f32))
+                })
+                .collect(),
+            CraterShape::Square => square
+                .map(|(i, j)| ((i, j), relative(i.abs().max(j.abs()) as f32, r as f32)))
+                .collect(),
+            CraterShape::Custom(offsets) => {
+                let length = |(i, j): (i32, i32)| ((i * i + j * j) as f32).sqrt();
+                let size = offsets.iter().cloned().map(length).fold(0.0, f32::max);
+                offsets
+                    .iter()
+                    .map(|offset| (*offset, relative(length(*offset), size)))
+                    .collect()
+            }
+        }
+    }
+
+    /// Blasts the crater into `grid` around `center`. Cells that are `solid` turn into `air`,
+    /// or into `debris` in the falloff ring, other cells and cells outside the grid are left
+    /// alone. Returns every cell that changed, once.
+    pub fn blast(
+        &self,
+        grid: &mut Grid<Reality>,
+        center: (i32, i32),
+        solid: fn(&Reality) -> bool,
+        air: Reality,
+        debris: Reality,
+    ) -> Vec<(u32, u32, Reality)> {
+        let (width, height) = grid.get_size();
+        let mut changes = Vec::new();
+        for ((i, j), distance) in self.stamp() {
+            let (x, y) = (center.0 + i, center.1 + j);
+            if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                continue;
+            }
+            let (x, y) = (x as usize, y as usize);
+            let value = if distance > 1.0 - self.falloff {
+                debris
+            } else {
+                air
+            };
+            match grid.get(x, y) {
+                // A cell that changed is no longer solid, so offsets listed twice are skipped
+                Some(cell) if solid(cell) && *cell != value => {
+                    grid.set(x, y, value);
+                    changes.push((x as u32, y as u32, value));
+                }
+                _ => {}
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(cell: &Reality) -> bool {
+        *cell != 0
+    }
+
+    fn solid_grid(size: usize) -> Grid<Reality> {
+        let mut grid = Grid::new();
+        grid.resize(size, size);
+        for i in 0..size {
+            for j in 0..size {
+                grid.set(i, j, 200);
+            }
+        }
+        grid
+    }
+
+    fn crater(shape: CraterShape, radius: i32) -> Crater {
+        Crater {
+            shape,
+            radius,
+            falloff: 0.0,
+        }
+    }
+
+    #[test]
+    fn circle_and_square_sizes() {
+        let mut grid = solid_grid(20);
+        let circle = crater(CraterShape::Circle, 3);
+        assert_eq![29, circle.blast(&mut grid, (5, 5), solid, 0, 100).len()];
+        assert_eq![Some(&0), grid.get(5, 8)];
+        assert_eq![Some(&200), grid.get(8, 8)];
+
+        let square = crater(CraterShape::Square, 3);
+        assert_eq![49, square.blast(&mut grid, (14, 14), solid, 0, 100).len()];
+    }
+
+    #[test]
+    fn craters_are_clipped_to_grid() {
+        let mut grid = solid_grid(10);
+        let changes = crater(CraterShape::Circle, 3).blast(&mut grid, (0, 9), solid, 0, 100);
+        assert_eq![11, changes.len()];
+        assert![changes.iter().all(|(x, y, _)| *x < 10 && *y < 10)];
+    }
+
+    #[test]
+    fn only_changed_cells_are_reported() {
+        let mut grid = solid_grid(20);
+        let custom = crater(CraterShape::Custom(vec![(0, 0), (1, 0), (0, 0)]), 0);
+        assert_eq![
+            vec![(5, 5, 0), (6, 5, 0)],
+            custom.blast(&mut grid, (5, 5), solid, 0, 100)
+        ];
+
+        let circle = crater(CraterShape::Circle, 2);
+        assert_eq![13 - 2, circle.blast(&mut grid, (5, 5), solid, 0, 100).len()];
+        assert![circle.blast(&mut grid, (5, 5), solid, 0, 100).is_empty()];
+    }
+
+    #[test]
+    fn falloff_leaves_debris_at_the_rim() {
+        let mut grid = solid_grid(20);
+        let crater = Crater {
+            falloff: 0.5,
+            ..crater(CraterShape::Circle, 2)
+        };
+        let changes = crater.blast(&mut grid, (5, 5), solid, 0, 100);
+        let cleared = changes.iter().filter(|(_, _, cell)| *cell == 0).count();
+        assert_eq![(5, 8), (cleared, changes.len() - cleared)];
+        assert_eq![Some(&100), grid.get(7, 5)];
+    }
+}
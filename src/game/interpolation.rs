@@ -0,0 +1,242 @@
+use crate::game::{Bullet, PlayerData};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of snapshots kept per entity, regardless of how old they are
+const MAX_SNAPSHOTS: usize = 64;
+/// How far a single late snapshot moves the estimate of the server time back
+const CLOCK_DRIFT_PER_SNAPSHOT: Duration = Duration::from_micros(100);
+
+/// Types that can be blended between two snapshots.
+///
+/// `alpha` is 0 at `self` and 1 at `other`. Values above 1 extrapolate past `other`.
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self;
+}
+
+impl Interpolate for PlayerData {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        PlayerData {
+            position: self.position + (other.position - self.position).scale_uni(alpha),
+            ..other.clone()
+        }
+    }
+}
+
+impl Interpolate for Bullet {
+    fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+        Bullet {
+            position: self.position + (other.position - self.position).scale_uni(alpha),
+            ..other.clone()
+        }
+    }
+}
+
+/// Server snapshots of a single entity, keyed by the server time of the tick they were taken
+/// at, so network jitter doesn't affect their spacing.
+///
+/// Used to render remote entities slightly in the past, so there is nearly always a snapshot on
+/// either side of the render time to interpolate between.
+#[derive(Debug)]
+pub struct SnapshotBuffer<T> {
+    snapshots: VecDeque<(Duration, T)>,
+}
+
+impl<T> Default for SnapshotBuffer<T> {
+    fn default() -> Self {
+        SnapshotBuffer {
+            snapshots: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Clone + Interpolate> SnapshotBuffer<T> {
+    /// Record a snapshot taken at server time `time`. A snapshot of the same tick replaces the
+    /// previous one, snapshots older than the newest one arrived out of order and are dropped.
+    pub fn push(&mut self, time: Duration, snapshot: T) {
+        match self.snapshots.back_mut() {
+            Some((last, value)) if *last == time => {
+                *value = snapshot;
+            }
+            Some((last, _)) if *last > time => {}
+            _ => {
+                self.snapshots.push_back((time, snapshot));
+            }
+        }
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Get the entity state at `time`, discarding snapshots that are no longer needed.
+    ///
+    /// Interpolates between the two snapshots surrounding `time`. When `time` lies past the newest
+    /// snapshot, the movement between the two newest snapshots is extrapolated for at most
+    /// `max_extrapolation`, after which the entity stays put.
+    pub fn sample(&mut self, time: Duration, max_extrapolation: Duration) -> Option<T> {
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+
+        match self.snapshots.iter().position(|(stamp, _)| *stamp > time) {
+            Some(0) => self.snapshots.front().map(|(_, value)| value.clone()),
+            Some(idx) => {
+                let (begin, from) = &self.snapshots[idx - 1];
+                let (end, to) = &self.snapshots[idx];
+                let alpha = (time - *begin).as_secs_f32() / (*end - *begin).as_secs_f32();
+                Some(from.interpolate(to, alpha))
+            }
+            None => {
+                let len = self.snapshots.len();
+                if len < 2 {
+                    return self.snapshots.back().map(|(_, value)| value.clone());
+                }
+                let (begin, from) = &self.snapshots[len - 2];
+                let (end, to) = &self.snapshots[len - 1];
+                let overshoot = (time - *end).min(max_extrapolation);
+                let alpha = 1.0 + overshoot.as_secs_f32() / (*end - *begin).as_secs_f32();
+                Some(from.interpolate(to, alpha))
+            }
+        }
+    }
+
+    /// The most recently received snapshot
+    pub fn latest(&self) -> Option<&T> {
+        self.snapshots.back().map(|(_, value)| value)
+    }
+}
+
+/// Estimate of the current server time, from the server time of received snapshots and when
+/// they arrived.
+///
+/// Follows the snapshot that arrived fastest, so late snapshots don't make the clock jitter.
+/// Every late snapshot moves the estimate back a little, so the clock recovers when the latency
+/// grows for good.
+#[derive(Debug, Default)]
+pub struct ServerClock {
+    /// A local instant and the server time it corresponds to
+    reference: Option<(Instant, Duration)>,
+}
+
+impl ServerClock {
+    /// Registers that a snapshot of server time `server_time` arrived at `arrival`
+    pub fn observe(&mut self, server_time: Duration, arrival: Instant) {
+        match self.server_time(arrival) {
+            Some(estimate) if estimate > server_time => {
+                let late = (estimate - server_time).min(CLOCK_DRIFT_PER_SNAPSHOT);
+                if let Some((_, reference)) = self.reference.as_mut() {
+                    *reference = reference
+                        .checked_sub(late)
+                        .unwrap_or_else(|| Duration::new(0, 0));
+                }
+            }
+            _ => self.reference = Some((arrival, server_time)),
+        }
+    }
+
+    /// The server time at `now`, `None` before the first snapshot
+    pub fn server_time(&self, now: Instant) -> Option<Duration> {
+        self.reference
+            .map(|(instant, server_time)| server_time + now.saturating_duration_since(instant))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Scalar(f32);
+
+    impl Interpolate for Scalar {
+        fn interpolate(&self, other: &Self, alpha: f32) -> Self {
+            Scalar(self.0 + (other.0 - self.0) * alpha)
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn empty_buffer_has_no_sample() {
+        let mut buffer: SnapshotBuffer<Scalar> = SnapshotBuffer::default();
+        assert_eq![None, buffer.sample(ms(0), ms(100))];
+    }
+
+    #[test]
+    fn interpolates_between_surrounding_snapshots() {
+        let start = ms(1000);
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(start, Scalar(0.0));
+        buffer.push(start + ms(100), Scalar(10.0));
+        buffer.push(start + ms(200), Scalar(30.0));
+
+        assert_eq![Some(Scalar(5.0)), buffer.sample(start + ms(50), ms(0))];
+        assert_eq![Some(Scalar(20.0)), buffer.sample(start + ms(150), ms(0))];
+    }
+
+    #[test]
+    fn holds_oldest_snapshot_before_buffer_start() {
+        let start = ms(1000);
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(start + ms(100), Scalar(1.0));
+        buffer.push(start + ms(200), Scalar(2.0));
+
+        assert_eq![Some(Scalar(1.0)), buffer.sample(start, ms(0))];
+    }
+
+    #[test]
+    fn extrapolation_is_limited() {
+        let start = ms(1000);
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(start, Scalar(0.0));
+        buffer.push(start + ms(100), Scalar(10.0));
+
+        assert_eq![Some(Scalar(15.0)), buffer.sample(start + ms(150), ms(1000))];
+        assert_eq![Some(Scalar(12.0)), buffer.sample(start + ms(500), ms(20))];
+    }
+
+    #[test]
+    fn snapshots_of_same_tick_replace_each_other() {
+        let start = ms(1000);
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(start, Scalar(0.0));
+        buffer.push(start, Scalar(3.0));
+
+        assert_eq![Some(&Scalar(3.0)), buffer.latest()];
+        assert_eq![Some(Scalar(3.0)), buffer.sample(start + ms(10), ms(100))];
+    }
+
+    #[test]
+    fn out_of_order_snapshots_are_dropped() {
+        let start = ms(1000);
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(start + ms(100), Scalar(1.0));
+        buffer.push(start, Scalar(0.0));
+
+        assert_eq![Some(&Scalar(1.0)), buffer.latest()];
+        assert_eq![Some(Scalar(1.0)), buffer.sample(start, ms(0))];
+    }
+
+    #[test]
+    fn server_clock_follows_fastest_snapshot() {
+        let start = Instant::now();
+        let mut clock = ServerClock::default();
+        assert_eq![None, clock.server_time(start)];
+
+        clock.observe(ms(5000), start);
+        assert_eq![Some(ms(5050)), clock.server_time(start + ms(50))];
+
+        // Arrived 30 ms late, only moves the clock back a little
+        clock.observe(ms(5008), start + ms(38));
+        assert_eq![
+            Some(ms(5050) - CLOCK_DRIFT_PER_SNAPSHOT),
+            clock.server_time(start + ms(50))
+        ];
+
+        // Arrived faster than any snapshot before
+        clock.observe(ms(5040), start + ms(30));
+        assert_eq![Some(ms(5060)), clock.server_time(start + ms(50))];
+    }
+}
@@ -0,0 +1,71 @@
+use crate::game::{Id, Team};
+
+/// Phase of a match. A match starts in warmup, runs until the score or time limit is reached,
+/// then ends. After the restart delay the world is regenerated and the next match starts in
+/// warmup again.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum MatchState {
+    /// Players can move and fight, but bases can't be attacked and nothing is scored
+    Warmup,
+    Running,
+    /// `winner` is `None` when the time ran out with both teams on the same score
+    Ended {
+        winner: Option<Team>,
+    },
+}
+
+impl Default for MatchState {
+    fn default() -> MatchState {
+        MatchState::Warmup
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PlayerScore {
+    pub id: Id,
+    pub team: Team,
+    pub kills: u32,
+    pub deaths: u32,
+}
+
+/// Standings of the current match, sent to clients when they change or on request
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Scoreboard {
+    pub state: MatchState,
+    /// Rounds won by each team, indexed by `Team::index`
+    pub team_scores: [u32; 2],
+    pub players: Vec<PlayerScore>,
+}
+
+impl Scoreboard {
+    /// Players sorted by kills, most first, with fewer deaths breaking ties
+    pub fn ranking(&self) -> Vec<&PlayerScore> {
+        let mut ranking: Vec<&PlayerScore> = self.players.iter().collect();
+        ranking.sort_by(|a, b| b.kills.cmp(&a.kills).then(a.deaths.cmp(&b.deaths)));
+        ranking
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(id: Id, kills: u32, deaths: u32) -> PlayerScore {
+        PlayerScore {
+            id,
+            kills,
+            deaths,
+            ..PlayerScore::default()
+        }
+    }
+
+    #[test]
+    fn ranking_orders_by_kills_then_deaths() {
+        let scoreboard = Scoreboard {
+            players: vec![score(0, 1, 0), score(1, 3, 2), score(2, 3, 1)],
+            ..Scoreboard::default()
+        };
+        let ids: Vec<Id> = scoreboard.ranking().iter().map(|x| x.id).collect();
+        assert_eq![vec![2, 1, 0], ids];
+    }
+}
@@ -1,52 +1,48 @@
 use crate::game::*;
 use bimap::BiMap;
-use cgmath::*;
-use fast_logger::{GenericLogger, Logger};
-use geometry::{grid2d::Grid, vec::Vec2};
+use fast_logger::{debug, Logger};
+use geometry::{bocs::Bocs, cam::Camera, grid2d::Grid, vec::Vec2};
 use laminar::{Packet, SocketEvent};
 use rand::Rng;
 use rand_pcg::Pcg64Mcg;
-use std::net::SocketAddr;
-use std::time::Instant;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
 
-const WORLD_WIDTH: usize = 1000;
-const WORLD_HEIGHT: usize = 1000;
+/// Default size of the world, clients prepare their terrain layer for it
+pub const WORLD_WIDTH: usize = 1000;
+pub const WORLD_HEIGHT: usize = 1000;
 const WORLD_SEED: [f32; 3] = [0.0, 0.0, 0.0];
+/// Number of snapshots sent to a client that are kept as possible delta baselines
+const MAX_SENT_SNAPSHOTS: usize = 64;
+/// Ticks a dead player waits before it respawns, 3 seconds
+const RESPAWN_TICKS: u64 = 375;
+/// Random positions tried when looking for free space to spawn in
+const SPAWN_ATTEMPTS: usize = 100;
+/// Ticks between the end of a round and the start of the next, 5 seconds
+const ROUND_RESTART_TICKS: u64 = 625;
+/// Inputs a player can have waiting to be applied, older ones are merged into newer ones
+const MAX_QUEUED_INPUTS: usize = 32;
 
-fn generate_world(w: usize, h: usize, seed: [f32; 3], mut logger: Logger<Log>) -> Grid<Reality> {
-    let mut grid = Grid::default();
-    grid.resize(w, h);
-    logger.info("Initializing graphics");
-    let mut windowing = VxDraw::new(
-        logger.clone_add_context("vxdraw").to_compatibility(),
-        ShowWindow::Headless1k,
-    );
-
-    {
-        static BACKGROUND: &dyntex::ImgData =
-            &dyntex::ImgData::PNGBytes(include_bytes!["../../assets/images/terrabackground.png"]);
-        let background = windowing.dyntex().add_layer(
-            BACKGROUND,
-            &dyntex::LayerOptions::new()
-                .depth(true)
-                .fixed_perspective(Matrix4::identity()),
-        );
-        windowing.dyntex().add(&background, dyntex::Sprite::new());
-    }
-
-    let mut strtex = windowing.strtex();
-
-    let tex = strtex.add_layer(&strtex::LayerOptions::new().width(w).height(h).depth(false));
+/// Startup parameters of a server that can't be changed while it's running
+#[derive(Clone, Debug)]
+pub struct ServerSettings {
+    /// Address to bind the socket to. Port 0 picks any free port.
+    pub address: SocketAddr,
+    pub world_seed: [f32; 3],
+    pub world_width: usize,
+    pub world_height: usize,
+}
 
-    strtex.fill_with_perlin_noise(&tex, seed);
-    strtex.read(&tex, |x, pitch| {
-        for j in 0..w {
-            for i in 0..h {
-                grid.set(i, j, x[i + j * pitch].0);
-            }
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            address: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0)),
+            world_seed: WORLD_SEED,
+            world_width: WORLD_WIDTH,
+            world_height: WORLD_HEIGHT,
         }
-    });
-    grid
+    }
 }
 
 pub struct Server {
@@ -55,29 +51,106 @@ pub struct Server {
     pub random: Pcg64Mcg,
     pub time: Instant,
     pub config: ServerConfig,
+    pub timestep: FixedTimestep,
     // Communication
-    pub network: Socket,
+    pub network: ConditionedSocket,
     pub connections: BiMap<Id, SocketAddr>,
+    pub clients: HashMap<Id, RemoteClient>,
+    /// Number of the most recently sent snapshot
+    pub snapshot: u32,
+    /// Round status as last sent to clients
+    pub sent_round: RoundStatus,
+    /// Scoreboard as last sent to clients
+    pub sent_scoreboard: Scoreboard,
+}
+
+/// Replication state of a connected client
+#[derive(Debug, Default)]
+pub struct RemoteClient {
+    /// Last snapshot the client confirmed to have received
+    pub acked_snapshot: Option<u32>,
+    /// Snapshots recently sent to the client, the baselines for delta encoding
+    pub sent_snapshots: VecDeque<(u32, Snapshot)>,
+    /// What the client is looking at, `None` until the client reports it
+    pub view: Option<Camera>,
+    /// Grid changes outside the area of interest, sent once they come into view. Also holds
+    /// every grid change while the terrain is streamed. Only the latest change of a cell is
+    /// kept, so it never grows beyond the size of the grid.
+    pub pending_grid_changes: HashMap<(u32, u32), Reality>,
+    /// Next terrain chunk to send, `None` once the client has the whole terrain
+    pub terrain_stream: Option<usize>,
+    /// Terrain chunks the client asked to be sent again
+    pub requested_chunks: BTreeSet<usize>,
+}
+
+impl RemoteClient {
+    fn acknowledge(&mut self, snapshot: u32) {
+        if self.acked_snapshot.map_or(true, |acked| snapshot > acked) {
+            self.acked_snapshot = Some(snapshot);
+            while let Some((oldest, _)) = self.sent_snapshots.front() {
+                if *oldest < snapshot {
+                    self.sent_snapshots.pop_front();
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The view of the client grown by `margin` in every direction
+    fn interest_region(&self, margin: f32) -> Option<Bocs> {
+        self.view.map(|view| {
+            let view = view.get_view_bocs();
+            Bocs {
+                start: view.start - Vec2::new(margin, margin),
+                difference: view.difference + Vec2::new(margin, margin).scale_uni(2.0),
+            }
+        })
+    }
+
+    fn baseline(&self) -> Option<(u32, &Snapshot)> {
+        let acked = self.acked_snapshot?;
+        self.sent_snapshots
+            .iter()
+            .find(|(number, _)| *number == acked)
+            .map(|(number, snapshot)| (*number, snapshot))
+    }
 }
 
 impl Server {
+    /// Creates a server listening on a random loopback port
     pub fn new(logger: Logger<Log>) -> Server {
+        Server::with_settings(logger, ServerSettings::default()).unwrap()
+    }
+
+    pub fn with_settings(logger: Logger<Log>, settings: ServerSettings) -> Result<Server, Error> {
         let mut cfg = laminar::Config::default();
         cfg.receive_buffer_max_size = cfg.max_packet_size;
+        let now = Instant::now();
         let mut s = Server {
-            logger: logger.clone(),
+            logger,
             logic: ServerLogic::default(),
             random: Pcg64Mcg::new(0),
-            time: Instant::now(),
+            time: now,
             config: Default::default(),
+            timestep: FixedTimestep::new(TICK_DURATION, now),
             //
-            network: random_port_socket(cfg),
+            network: ConditionedSocket::new(bind_socket(settings.address, cfg)?),
             connections: BiMap::new(),
+            clients: HashMap::new(),
+            snapshot: 0,
+            sent_round: RoundStatus::default(),
+            sent_scoreboard: Scoreboard::default(),
         };
-        s.logic.grid = generate_world(WORLD_WIDTH, WORLD_HEIGHT, WORLD_SEED, logger.clone());
-        create_black_square_around_player(&mut s.logic.grid);
+        s.logic.world_seed = settings.world_seed;
+        s.logic.grid = generate_world(
+            settings.world_width,
+            settings.world_height,
+            settings.world_seed,
+        );
+        s.logic.bases = Base::for_world(settings.world_width, settings.world_height).to_vec();
 
-        s
+        Ok(s)
     }
     /// Assigns `config.server` to `self.config` and `config.world` to `self.logic.config`.
     pub fn apply_config(&mut self, config: Config) {
@@ -86,13 +159,23 @@ impl Server {
         self.logic.config = w;
     }
 
+    /// Runs as many fixed-length simulation ticks as fit in the time since the last call
     pub fn tick_logic(&mut self) {
-        self.update_network();
-        self.logic
-            .update_players(&mut self.random, &mut self.logger);
-        self.logic.update_bullets();
+        for _ in 0..self.timestep.advance(self.time) {
+            self.update_network();
+            self.logic.update_inputs(&mut self.random, &mut self.logger);
+            self.logic.update_respawns(&mut self.random);
+            self.logic.update_players(&mut self.random);
+            self.logic.update_bullets();
+            self.logic.update_round(&mut self.random);
+            self.logic.update_match(&self.config, &mut self.random);
+            self.logic.tick += 1;
+        }
+    }
 
-        std::thread::sleep(std::time::Duration::new(0, 8_000_000));
+    /// Time until `tick_logic` has another tick to run
+    pub fn until_next_tick(&self, now: Instant) -> Duration {
+        self.timestep.until_next_tick(now)
     }
 
     fn update_network(&mut self) {
@@ -106,136 +189,454 @@ impl Server {
                         match msg {
                             ClientMessage::Join => {
                                 info![self.logger, "Received Join message"];
-                                let id = self.logic.add_player();
-
-                                self.connections.insert(id, pkt.addr());
+                                // Join may be resent while waiting for Welcome
+                                let id = match self.connections.get_by_right(&pkt.addr()) {
+                                    Some(id) => *id,
+                                    None => {
+                                        let id = self.logic.add_player();
+                                        self.connections.insert(id, pkt.addr());
+                                        self.clients.insert(
+                                            id,
+                                            RemoteClient {
+                                                terrain_stream: Some(0),
+                                                ..RemoteClient::default()
+                                            },
+                                        );
+                                        id
+                                    }
+                                };
 
+                                let (world_width, world_height) = self.logic.grid.get_size();
+                                // Ordered, so it arrives before the terrain chunks
                                 self.network
-                                    .send(Packet::reliable_unordered(
+                                    .send(Packet::reliable_ordered(
                                         pkt.addr(),
                                         ServerMessage::Welcome {
                                             your_id: id,
-                                            world_width: WORLD_WIDTH,
-                                            world_height: WORLD_HEIGHT,
-                                            world_seed: WORLD_SEED,
+                                            world_width,
+                                            world_height,
+                                            weapons: self.logic.weapons.clone(),
+                                            world: self.logic.world,
+                                            // The state updates of this tick are the first ones
+                                            tick: self.logic.tick,
                                         }
                                         .serialize(),
+                                        None,
                                     ))
                                     .unwrap_or_else(|_| {
                                         error![self.logger, "Failed to send Welcome packet"];
                                     });
+                                self.network
+                                    .send(Packet::reliable_ordered(
+                                        pkt.addr(),
+                                        ServerMessage::Round(self.logic.round.clone()).serialize(),
+                                        None,
+                                    ))
+                                    .unwrap_or_else(|_| {
+                                        error![self.logger, "Failed to send Round packet"];
+                                    });
                             }
-                            ClientMessage::Input(commands) => {
-                                let id = self.connections.get_by_right(&pkt.addr());
-                                match id {
-                                    Some(id) => {
-                                        if let Some(player) = self
-                                            .logic
-                                            .players
-                                            .iter_mut()
-                                            .find(|player| player.id == *id)
-                                        {
-                                            for cmd in commands {
-                                                match cmd {
-                                                    InputCommand::Keyboard {
-                                                        state,
-                                                        virtual_keycode,
-                                                        modifiers,
-                                                    } => {
-                                                        player.input.register_key(
-                                                            &winit::KeyboardInput {
-                                                                scancode: 0,
-                                                                state,
-                                                                virtual_keycode: Some(
-                                                                    virtual_keycode,
-                                                                ),
-                                                                modifiers,
-                                                            },
-                                                        );
-                                                    }
-                                                    InputCommand::Mouse {
-                                                        position,
-                                                        state,
-                                                        button,
-                                                        modifiers,
-                                                    } => {
-                                                        player.input.register_mouse_position(
-                                                            position.0, position.1,
-                                                        );
-                                                        player.input.register_mouse_input(
-                                                            winput::MouseInput { state, modifiers },
-                                                            button,
-                                                        );
-                                                    }
-                                                }
-                                            }
-                                        }
+                            ClientMessage::Leave => {
+                                info![self.logger, "Received Leave message"];
+                                self.disconnect(&pkt.addr());
+                            }
+                            ClientMessage::AckSnapshot(snapshot) => {
+                                if let Some(id) = self.connections.get_by_right(&pkt.addr()) {
+                                    if let Some(client) = self.clients.get_mut(id) {
+                                        client.acknowledge(snapshot);
                                     }
-                                    None => {
-                                        error![
-                                            self.logger,
-                                            "Unregistered client sent Input message"
-                                        ];
+                                }
+                            }
+                            ClientMessage::View(camera) => {
+                                if let Some(id) = self.connections.get_by_right(&pkt.addr()) {
+                                    if let Some(client) = self.clients.get_mut(id) {
+                                        client.view = Some(camera);
                                     }
                                 }
                             }
+                            ClientMessage::RequestScoreboard => {
+                                self.network
+                                    .send(Packet::reliable_ordered(
+                                        pkt.addr(),
+                                        ServerMessage::Scoreboard(self.logic.scoreboard())
+                                            .serialize(),
+                                        None,
+                                    ))
+                                    .unwrap_or_else(|_| {
+                                        error![self.logger, "Failed to send Scoreboard packet"];
+                                    });
+                            }
+                            ClientMessage::RequestTerrainChunks(chunks) => {
+                                if let Some(id) = self.connections.get_by_right(&pkt.addr()) {
+                                    let (width, height) = self.logic.grid.get_size();
+                                    let count = chunk_count(width, height);
+                                    if let Some(client) = self.clients.get_mut(id) {
+                                        client.requested_chunks.extend(
+                                            chunks
+                                                .into_iter()
+                                                .map(|index| index as usize)
+                                                .filter(|index| *index < count),
+                                        );
+                                    }
+                                }
+                            }
+                            ClientMessage::SwitchWeapon(weapon) => {
+                                if let Some(id) = self.connections.get_by_right(&pkt.addr()) {
+                                    let id = *id;
+                                    if !self.logic.handle_weapon_switch(id, weapon) {
+                                        debug![self.logger, "Rejected weapon switch"; "id" => id, "weapon" => InDebug(&weapon)];
+                                    }
+                                }
+                            }
+                            ClientMessage::Input {
+                                sequence,
+                                commands,
+                                aim,
+                            } => match self.connections.get_by_right(&pkt.addr()) {
+                                Some(id) => {
+                                    let id = *id;
+                                    if !self.logic.handle_input(id, sequence, commands, aim) {
+                                        debug![self.logger, "Dropped outdated input"; "id" => id, "sequence" => sequence];
+                                    }
+                                }
+                                None => {
+                                    error![self.logger, "Unregistered client sent Input message"];
+                                }
+                            },
                         }
                     } else {
                         error![self.logger, "Failed to deserialize an incoming message"];
                     }
                 }
                 Some(SocketEvent::Connect(_addr)) => {}
-                Some(SocketEvent::Timeout(_addr)) => {} /*TODO*/
+                Some(SocketEvent::Timeout(addr)) => {
+                    info![self.logger, "Client timed out"; "address" => addr];
+                    self.disconnect(&addr);
+                }
                 None => break,
             }
         }
         // Send state updates
-        let players: Vec<_> = self.logic.players.iter().map(|p| p.inner.clone()).collect();
-        let state_data = ServerMessage::State {
-            players: players.clone(),
+        self.snapshot = self.snapshot.wrapping_add(1);
+        let snapshot = Snapshot {
+            players: self
+                .logic
+                .players
+                .iter()
+                .filter(|p| p.is_alive())
+                .map(|p| p.inner.clone())
+                .collect(),
             bullets: self.logic.bullets.clone(),
+        };
+        let force_full_state = self.config.ticks_per_full_state > 0
+            && self.snapshot % self.config.ticks_per_full_state == 0;
+        let round_changed = self.logic.round != self.sent_round;
+        let scoreboard = self.logic.scoreboard();
+        let scoreboard_changed = scoreboard != self.sent_scoreboard;
+        let (world_width, world_height) = self.logic.grid.get_size();
+        let new_world = ServerMessage::NewWorld {
+            world_width,
+            world_height,
+            world_seed: self.logic.world_seed,
+            world: self.logic.world,
         }
         .serialize();
+        let terrain_chunks = chunk_count(world_width, world_height);
+        let chunks_per_tick = self.config.terrain_chunks_per_tick.max(1) as usize;
+        let hash_interval = seconds_to_ticks(self.config.terrain_hash_interval);
+        let hashes: Option<Vec<u64>> = if hash_interval > 0 && self.logic.tick % hash_interval == 0
+        {
+            Some(
+                (0..terrain_chunks)
+                    .map(|index| chunk_hash(&self.logic.grid, index))
+                    .collect(),
+            )
+        } else {
+            None
+        };
 
-        let delta_data = ServerMessage::DeltaState {
-            removed: self.logic.removed.clone(),
-            grid_changes: self.logic.grid_changes.clone(),
-        }
-        .serialize();
+        for (id, cli_addr) in self.connections.iter() {
+            let last_input_sequence = self
+                .logic
+                .players
+                .iter()
+                .find(|player| player.id == *id)
+                .map_or(0, |player| player.last_input_sequence);
+            let client = self.clients.entry(*id).or_default();
+            if self.logic.new_world {
+                // Sent before anything else of this tick, so it arrives before the new world
+                // changes and the respawn events
+                client.pending_grid_changes.clear();
+                self.network
+                    .send(Packet::reliable_ordered(*cli_addr, new_world.clone(), None))
+                    .unwrap();
+                client.requested_chunks.clear();
+                // A client that was still receiving the old terrain receives the new one instead
+                if client.terrain_stream.is_some() {
+                    client.terrain_stream = Some(0);
+                }
+            }
+            let mut budget = chunks_per_tick;
+            if let Some(next) = client.terrain_stream {
+                let end = (next + budget).min(terrain_chunks);
+                for index in next..end {
+                    send_terrain_chunk(&mut self.network, *cli_addr, &self.logic.grid, index);
+                }
+                budget -= end - next;
+                client.terrain_stream = if end < terrain_chunks {
+                    Some(end)
+                } else {
+                    None
+                };
+            }
+            let resent: Vec<usize> = client
+                .requested_chunks
+                .iter()
+                .take(budget)
+                .cloned()
+                .collect();
+            for index in resent {
+                client.requested_chunks.remove(&index);
+                send_terrain_chunk(&mut self.network, *cli_addr, &self.logic.grid, index);
+            }
+            let region = client.interest_region(self.config.interest_margin);
+            let snapshot = filter_snapshot(&snapshot, region, *id);
 
-        for cli_addr in self.connections.right_values() {
+            let mut removed = self.logic.removed.clone();
+            if let Some((_, previous)) = client.sent_snapshots.back() {
+                for left in left_region(previous, &snapshot) {
+                    if !removed.contains(&left) {
+                        removed.push(left);
+                    }
+                }
+            }
+            client.pending_grid_changes.extend(
+                self.logic
+                    .grid_changes
+                    .iter()
+                    .map(|(x, y, value)| ((*x, *y), *value)),
+            );
+            let grid_changes = match region {
+                // Chunks are taken from the current grid, so changes to chunks that were already
+                // sent are held until the client has the whole terrain and replayed after it
+                _ if client.terrain_stream.is_some() => Vec::new(),
+                Some(region) => {
+                    let (inside, outside): (HashMap<_, _>, HashMap<_, _>) = client
+                        .pending_grid_changes
+                        .drain()
+                        .partition(|((x, y), _)| {
+                            region.is_point_inside(Vec2::new(*x as f32, *y as f32))
+                        });
+                    client.pending_grid_changes = outside;
+                    inside
+                        .into_iter()
+                        .map(|((x, y), value)| (x, y, value))
+                        .collect()
+                }
+                None => client
+                    .pending_grid_changes
+                    .drain()
+                    .map(|((x, y), value)| (x, y, value))
+                    .collect(),
+            };
+            if let (Some(hashes), None) = (&hashes, client.terrain_stream) {
+                // Chunks with changes that are still held back are left out, the client compares
+                // the others once it has the changes of this tick
+                let mut hashes: Vec<Option<u64>> = hashes.iter().cloned().map(Some).collect();
+                for (x, y) in client.pending_grid_changes.keys() {
+                    hashes[chunk_index(*x as usize, *y as usize, world_width)] = None;
+                }
+                for index in &client.requested_chunks {
+                    hashes[*index] = None;
+                }
+                self.network
+                    .send(Packet::reliable_ordered(
+                        *cli_addr,
+                        ServerMessage::TerrainHashes {
+                            tick: self.logic.tick,
+                            hashes,
+                        }
+                        .serialize(),
+                        None,
+                    ))
+                    .unwrap();
+            }
+            let delta_data = ServerMessage::DeltaState {
+                removed,
+                grid_changes: encode_grid_changes(&grid_changes),
+                world: self.logic.world,
+                tick: self.logic.tick,
+            }
+            .serialize();
             self.network
-                .send(Packet::reliable_unordered(*cli_addr, delta_data.clone()))
+                .send(Packet::reliable_unordered(*cli_addr, delta_data))
                 .unwrap();
-        }
-        for cli_addr in self.connections.right_values() {
+            if !self.logic.events.is_empty() {
+                let events = ServerMessage::Events(self.logic.events.clone()).serialize();
+                self.network
+                    .send(Packet::reliable_ordered(*cli_addr, events, None))
+                    .unwrap();
+            }
+            if round_changed {
+                let round = ServerMessage::Round(self.logic.round.clone()).serialize();
+                self.network
+                    .send(Packet::reliable_ordered(*cli_addr, round, None))
+                    .unwrap();
+            }
+            if scoreboard_changed {
+                let scoreboard = ServerMessage::Scoreboard(scoreboard.clone()).serialize();
+                self.network
+                    .send(Packet::reliable_ordered(*cli_addr, scoreboard, None))
+                    .unwrap();
+            }
+
+            let baseline = if force_full_state {
+                None
+            } else {
+                client.baseline()
+            };
+            let data = match encode_snapshot(&snapshot, baseline.map(|(_, x)| x)) {
+                Ok(data) => data,
+                Err(_) => {
+                    error![self.logger, "Failed to encode snapshot"; "client" => id];
+                    continue;
+                }
+            };
+            let state_data = ServerMessage::State {
+                snapshot: self.snapshot,
+                tick: self.logic.tick,
+                baseline: baseline.map(|(number, _)| number),
+                last_input_sequence,
+                data,
+            }
+            .serialize();
+            client.sent_snapshots.push_back((self.snapshot, snapshot));
+            if client.sent_snapshots.len() > MAX_SENT_SNAPSHOTS {
+                client.sent_snapshots.pop_front();
+            }
             self.network
-                .send(Packet::unreliable_sequenced(
-                    *cli_addr,
-                    state_data.clone(),
-                    None,
-                ))
+                .send(Packet::unreliable_sequenced(*cli_addr, state_data, None))
                 .unwrap();
         }
 
         // Cleanup / reset state
+        if round_changed {
+            self.sent_round = self.logic.round.clone();
+        }
+        if scoreboard_changed {
+            self.sent_scoreboard = scoreboard;
+        }
+        self.logic.new_world = false;
         self.logic.grid_changes = Vec::new();
         self.logic.removed = Vec::new();
+        self.logic.events = Vec::new();
+    }
+
+    /// Forgets the client at `addr` and removes its player from the game
+    fn disconnect(&mut self, addr: &SocketAddr) {
+        if let Some((id, _)) = self.connections.remove_by_right(addr) {
+            info![self.logger, "Removing player"; "id" => id];
+            self.clients.remove(&id);
+            self.logic.remove_player(id);
+        }
+    }
+}
+
+/// Sends chunk `index` of `grid` on the ordered stream
+fn send_terrain_chunk(
+    network: &mut ConditionedSocket,
+    addr: SocketAddr,
+    grid: &Grid<Reality>,
+    index: usize,
+) {
+    let chunk = ServerMessage::TerrainChunk {
+        index: index as u32,
+        data: encode_chunk(grid, index),
     }
+    .serialize();
+    network
+        .send(Packet::reliable_ordered(addr, chunk, None))
+        .unwrap();
+}
+
+/// Keeps the entities inside `region`, and the player with id `own` regardless of where it is.
+/// The ammo and cooldown of other players are cleared, only their owner needs them.
+fn filter_snapshot(snapshot: &Snapshot, region: Option<Bocs>, own: Id) -> Snapshot {
+    let mut filtered = match region {
+        Some(region) => Snapshot {
+            players: snapshot
+                .players
+                .iter()
+                .filter(|player| player.id == own || region.is_point_inside(player.position))
+                .cloned()
+                .collect(),
+            bullets: snapshot
+                .bullets
+                .iter()
+                .filter(|bullet| region.is_point_inside(bullet.position))
+                .cloned()
+                .collect(),
+        },
+        None => snapshot.clone(),
+    };
+    for player in filtered
+        .players
+        .iter_mut()
+        .filter(|player| player.id != own)
+    {
+        player.curr_weapon_cooldown = 0;
+        player.ammo = 0;
+    }
+    filtered
+}
+
+/// Entities that were in `previous` but are missing from `current`
+fn left_region(previous: &Snapshot, current: &Snapshot) -> Vec<(Id, EntityType)> {
+    let players: HashSet<Id> = current.players.iter().map(|x| x.id).collect();
+    let bullets: HashSet<Id> = current.bullets.iter().map(|x| x.id).collect();
+    let left_players = previous
+        .players
+        .iter()
+        .filter(|x| !players.contains(&x.id))
+        .map(|x| (x.id, EntityType::Player));
+    let left_bullets = previous
+        .bullets
+        .iter()
+        .filter(|x| !bullets.contains(&x.id))
+        .map(|x| (x.id, EntityType::Bullet));
+    left_players.chain(left_bullets).collect()
 }
 
 #[derive(Default, Debug)]
 pub struct ServerLogic {
     pub grid: Grid<Reality>,
+    /// Seed `grid` was generated from, sent to clients so they can generate the same world
+    pub world_seed: [f32; 3],
+    /// Generation of `grid`, increased every time the world is regenerated
+    pub world: u32,
     pub players: Vec<ServerPlayer>,
     pub bullets: Vec<Bullet>,
     pub config: WorldConfig,
+    pub weapons: WeaponRegistry,
+    /// Bases of the teams, empty in worlds without a team game
+    pub bases: Vec<Base>,
+    pub round: RoundStatus,
+    pub match_state: MatchState,
+    /// Number of simulation ticks run so far
+    pub tick: u64,
+    /// Tick at which the next round starts, `None` while a round is running
+    next_round_tick: Option<u64>,
+    /// Tick at which `match_state` was entered
+    match_state_tick: u64,
+    /// Set when `grid` was replaced by a newly generated world this tick
+    new_world: bool,
     // ID counters
     player_id: Id,
     bullet_id: Id,
     //
     grid_changes: Vec<(u32, u32, Reality)>,
     removed: Vec<(Id, EntityType)>,
+    events: Vec<GameEvent>,
 }
 
 impl ServerLogic {
@@ -246,36 +647,368 @@ impl ServerLogic {
         }
     }
 
+    /// Adds a player to the team with fewer players, at the base of that team
     pub fn add_player(&mut self) -> Id {
         let id = self.player_id;
         self.player_id += 1;
+        let whites = self
+            .players
+            .iter()
+            .filter(|player| player.team == Team::White)
+            .count();
+        let team = if whites * 2 < self.players.len() {
+            Team::White
+        } else {
+            Team::Black
+        };
+        let position = self
+            .bases
+            .iter()
+            .find(|base| base.team == team)
+            .map_or(Vec2::null_vec(), Base::spawn_point);
         let player = ServerPlayer {
-            inner: PlayerData::new(id, 0, Vec2::null_vec()),
-            input: winput::Input::default(),
+            inner: PlayerData {
+                team,
+                ..PlayerData::new(id, team.reality(), position)
+            },
+            actions: ActionState::default(),
+            inputs: VecDeque::new(),
+            weapon_switch: None,
+            last_input_sequence: 0,
+            respawn_tick: None,
+            kills: 0,
+            deaths: 0,
         };
         self.players.push(player);
         id
     }
 
-    pub fn update_players(&mut self, random: &mut Pcg64Mcg, logger: &mut Logger<Log>) {
+    /// Queues the input commands of a single client tick, they are applied by `update_inputs`.
+    /// Returns false and drops the input if there is no such player, or if its sequence number
+    /// isn't newer than that of every input received before, like a resent one.
+    pub fn handle_input(
+        &mut self,
+        id: Id,
+        sequence: u32,
+        commands: Vec<InputCommand>,
+        aim: Option<f32>,
+    ) -> bool {
+        let player = match self.players.iter_mut().find(|player| player.id == id) {
+            Some(player) => player,
+            None => return false,
+        };
+        let newest = player
+            .inputs
+            .back()
+            .map_or(player.last_input_sequence, |input| input.sequence);
+        if sequence <= newest {
+            return false;
+        }
+        player.inputs.push_back(QueuedInput {
+            sequence,
+            commands,
+            aim,
+            switch: None,
+        });
+        // A client far ahead of the server doesn't get extra steps, only its commands
+        while player.inputs.len() > MAX_QUEUED_INPUTS {
+            if let Some(oldest) = player.inputs.pop_front() {
+                if let Some(next) = player.inputs.front_mut() {
+                    let newer = std::mem::replace(&mut next.commands, oldest.commands);
+                    next.commands.extend(newer);
+                    next.aim = next.aim.or(oldest.aim);
+                    next.switch = next.switch.or(oldest.switch);
+                }
+            }
+        }
+        true
+    }
+
+    /// Applies the oldest queued input of every player and advances the player by one
+    /// simulation step, mirroring the prediction done by the client. At most one input is
+    /// applied per player and tick, so sending inputs faster doesn't make a player faster.
+    /// Dead players only keep track of the input.
+    ///
+    /// Pressing `Action::SwitchReality` moves the player to the other reality and turns the
+    /// cells under it into air of that reality, so it isn't stuck inside terrain.
+    pub fn update_inputs(&mut self, random: &mut Pcg64Mcg, logger: &mut Logger<Log>) {
         for player in &mut self.players {
-            update_player(
-                &mut player.inner,
-                &player.input,
-                &self.config,
-                random,
-                &self.grid,
-                logger,
-            );
+            if let Some(weapon) = player.weapon_switch.take() {
+                hold_weapon(player, weapon, &self.weapons);
+            }
+            let QueuedInput {
+                sequence,
+                commands,
+                aim,
+                switch,
+            } = match player.inputs.pop_front() {
+                Some(input) => input,
+                None => continue,
+            };
+            player.weapon_switch = switch;
+            let switch_reality = commands.contains(&InputCommand::Pressed(Action::SwitchReality));
+            for cmd in commands {
+                player.actions.apply(cmd);
+            }
+            if let Some(aim) = aim {
+                player.aim = aim;
+            }
+            if player.is_alive() {
+                if switch_reality {
+                    player.reality = other_reality(player.reality);
+                    for (x, y) in hitbox_cells(player.position) {
+                        if self.grid.get(x, y).is_some() {
+                            let air = air_of(player.reality);
+                            self.grid.set(x, y, air);
+                            self.grid_changes.push((x as u32, y as u32, air));
+                        }
+                    }
+                }
+                update_player(
+                    &mut player.inner,
+                    &player.actions,
+                    &self.config,
+                    random,
+                    &self.grid,
+                    logger,
+                );
+            }
+            player.last_input_sequence = sequence;
+        }
+    }
+
+    /// Makes the player hold `weapon` once the inputs received before the switch have been
+    /// applied. Returns false if there is no such player or weapon.
+    pub fn handle_weapon_switch(&mut self, id: Id, weapon: Weapon) -> bool {
+        if !self.weapons.contains(weapon) {
+            return false;
+        }
+        match self.players.iter_mut().find(|player| player.id == id) {
+            Some(player) => {
+                match player.inputs.back_mut() {
+                    Some(input) => input.switch = Some(weapon),
+                    None => player.weapon_switch = Some(weapon),
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the player hold `weapon`, which starts out reloading. Returns false if there is no
+    /// such player or weapon, or the player already holds the weapon.
+    pub fn switch_weapon(&mut self, id: Id, weapon: Weapon) -> bool {
+        let weapons = &self.weapons;
+        match self.players.iter_mut().find(|player| player.id == id) {
+            Some(player) => hold_weapon(player, weapon, weapons),
+            None => false,
+        }
+    }
+
+    /// Removes a player and records the removal so clients can tear it down
+    pub fn remove_player(&mut self, id: Id) {
+        if let Some(idx) = self.players.iter().position(|player| player.id == id) {
+            self.players.swap_remove(idx);
+            self.removed.push((id, EntityType::Player));
+        }
+    }
+
+    /// Brings back dead players whose respawn delay has passed
+    pub fn update_respawns(&mut self, random: &mut Pcg64Mcg) {
+        for player in &mut self.players {
+            if player.respawn_tick.map_or(false, |tick| tick <= self.tick) {
+                respawn(player, &self.bases, &self.grid, random, &mut self.events);
+            }
+        }
+    }
+
+    /// Ends the round when a base is destroyed or captured, and starts the next one once the
+    /// restart delay has passed. Does nothing without bases or outside a running match.
+    pub fn update_round(&mut self, random: &mut Pcg64Mcg) {
+        if self.bases.is_empty() || self.match_state != MatchState::Running {
+            return;
+        }
+        if let Some(tick) = self.next_round_tick {
+            if tick <= self.tick {
+                self.start_round(random);
+            }
+            return;
+        }
+        for base in &self.bases {
+            let index = base.team.index();
+            if self.round.base_health[index] == 0 {
+                self.round
+                    .end(base.team.opponent(), RoundEnd::BaseDestroyed);
+                break;
+            }
+            let alive = self.players.iter().filter(|player| player.is_alive());
+            let (mut attackers, mut defenders) = (false, false);
+            for player in alive {
+                let center = player.position + Vec2::new(5.0, 5.0);
+                if player.team != base.team && base.core_contains(center) {
+                    attackers = true;
+                } else if player.team == base.team && base.area_contains(center) {
+                    defenders = true;
+                }
+            }
+            if attackers && !defenders {
+                self.round.capture_progress[index] += 1;
+            } else {
+                self.round.capture_progress[index] = 0;
+            }
+            if self.round.capture_progress[index] >= CAPTURE_TICKS {
+                self.round.end(base.team.opponent(), RoundEnd::BaseCaptured);
+                break;
+            }
+        }
+        if !self.round.is_running() {
+            self.next_round_tick = Some(self.tick + ROUND_RESTART_TICKS);
+        }
+    }
+
+    fn start_round(&mut self, random: &mut Pcg64Mcg) {
+        self.round.restart();
+        self.reset_arena(random);
+    }
+
+    /// Moves the match from warmup to running once both teams have players and the warmup
+    /// time has passed, ends it when a team reaches the score limit or the time runs out, and
+    /// starts the next match in a new world after the restart delay
+    pub fn update_match(&mut self, config: &ServerConfig, random: &mut Pcg64Mcg) {
+        let elapsed = self.tick - self.match_state_tick;
+        match self.match_state {
+            MatchState::Warmup => {
+                let both_teams = Team::ALL
+                    .iter()
+                    .all(|team| self.players.iter().any(|player| player.team == *team));
+                if !both_teams {
+                    self.match_state_tick = self.tick;
+                } else if elapsed >= seconds_to_ticks(config.warmup_time) {
+                    self.set_match_state(MatchState::Running);
+                    self.reset_scores();
+                    self.reset_arena(random);
+                }
+            }
+            MatchState::Running => {
+                let score_reached = config.score_limit > 0
+                    && self
+                        .round
+                        .scores
+                        .iter()
+                        .any(|score| *score >= config.score_limit);
+                let time_out =
+                    config.time_limit > 0.0 && elapsed >= seconds_to_ticks(config.time_limit);
+                if score_reached || time_out {
+                    let winner = self.round.leader();
+                    self.set_match_state(MatchState::Ended { winner });
+                }
+            }
+            MatchState::Ended { .. } => {
+                if elapsed >= seconds_to_ticks(config.restart_delay) {
+                    self.regenerate_world(random);
+                    self.set_match_state(MatchState::Warmup);
+                    self.reset_scores();
+                    self.reset_arena(random);
+                }
+            }
+        }
+    }
+
+    /// Kills, deaths and round wins of the current match
+    pub fn scoreboard(&self) -> Scoreboard {
+        Scoreboard {
+            state: self.match_state,
+            team_scores: self.round.scores,
+            players: self
+                .players
+                .iter()
+                .map(|player| PlayerScore {
+                    id: player.id,
+                    team: player.team,
+                    kills: player.kills,
+                    deaths: player.deaths,
+                })
+                .collect(),
+        }
+    }
+
+    fn set_match_state(&mut self, state: MatchState) {
+        self.match_state = state;
+        self.match_state_tick = self.tick;
+    }
+
+    fn reset_scores(&mut self) {
+        self.round = RoundStatus::default();
+        for player in &mut self.players {
+            player.kills = 0;
+            player.deaths = 0;
+        }
+    }
+
+    /// Replaces the world with a new one of the same size from a random seed
+    fn regenerate_world(&mut self, random: &mut Pcg64Mcg) {
+        let (width, height) = self.grid.get_size();
+        self.world_seed = [
+            random.gen_range(0.0, 1000.0),
+            random.gen_range(0.0, 1000.0),
+            random.gen_range(0.0, 1000.0),
+        ];
+        self.grid = generate_world(width, height, self.world_seed);
+        self.grid_changes.clear();
+        self.world += 1;
+        self.new_world = true;
+    }
+
+    /// Restores the bases, clears all bullets and puts every player back at its base
+    fn reset_arena(&mut self, random: &mut Pcg64Mcg) {
+        self.next_round_tick = None;
+        for base in &self.bases {
+            let changes = base.carve(&mut self.grid);
+            self.grid_changes.extend(changes);
+        }
+        for bullet in self.bullets.drain(..) {
+            self.removed.push((bullet.id, EntityType::Bullet));
+        }
+        for player in &mut self.players {
+            respawn(player, &self.bases, &self.grid, random, &mut self.events);
+        }
+    }
+
+    pub fn update_players(&mut self, random: &mut Pcg64Mcg) {
+        for player in &mut self.players {
+            if !player.is_alive() {
+                continue;
+            }
+            // Terrain of the other reality painted over the whole player pulls it over
+            let mut cells = hitbox_cells(player.position)
+                .filter_map(|(x, y)| self.grid.get(x, y))
+                .peekable();
+            if cells.peek().is_some() && cells.all(|cell| cell_reality(*cell) != player.reality) {
+                player.reality = other_reality(player.reality);
+            }
+
+            let stats = self.weapons.get(player.curr_weapon);
+            if player.curr_weapon_cooldown > 0 {
+                player.curr_weapon_cooldown -= 1;
+            }
+            if player.curr_weapon_cooldown == 0 && player.ammo == 0 {
+                player.ammo = stats.magazine_size;
+            }
 
             // Firing weapons
-            if player.input.is_mouse_button_down(winit::MouseButton::Left) {
-                let stats = player.curr_weapon.get_stats();
+            if player.actions.is_down(Action::Fire)
+                && player.curr_weapon_cooldown == 0
+                && player.ammo > 0
+            {
+                player.ammo -= 1;
+                player.curr_weapon_cooldown = if player.ammo == 0 {
+                    stats.reload_time
+                } else {
+                    stats.fire_interval
+                };
                 for _ in 0..stats.bullet_count {
-                    let angle = Vec2::from(player.input.get_mouse_position())
-                        - player.position
-                        - Vec2::new(5.0, 5.0);
-                    let direction = angle.rotate(random.gen_range(-stats.spread, stats.spread));
+                    let aim = player.aim + random.gen_range(-stats.spread, stats.spread);
+                    let direction = Vec2::new(aim.cos(), aim.sin());
 
                     let position = player.position + Vec2::new(5.0, 5.0);
                     let id = self.bullet_id;
@@ -285,6 +1018,9 @@ impl ServerLogic {
                         position,
                         id,
                         ty: player.curr_weapon,
+                        owner: player.id,
+                        reality: player.reality,
+                        age: 0,
                     });
                 }
             }
@@ -293,22 +1029,82 @@ impl ServerLogic {
 
     pub fn update_bullets(&mut self) {
         let mut to_remove = Vec::new();
+        // Bases can only be damaged while the match is running
+        let bases: &[Base] = if self.match_state == MatchState::Running {
+            &self.bases
+        } else {
+            &[]
+        };
         for (idx, b) in self.bullets.iter_mut().enumerate() {
-            let collision =
-                collision_test(&[b.position], None, b.direction, &self.grid, |x| *x > 0);
+            let owner_team = self
+                .players
+                .iter()
+                .find(|player| player.id == b.owner)
+                .map(|player| player.team);
+            let collision = collision_test(
+                &[b.position],
+                None,
+                b.direction,
+                &self.grid,
+                solid_for(b.reality),
+            );
             if let Some((xi, yi)) = collision {
                 to_remove.push(idx);
-                let area = b.ty.get_stats().destruction;
-                for i in -area..=area {
-                    for j in -area..=area {
-                        let pos = (xi as i32 + i, yi as i32 + j);
-                        let pos = (pos.0 as usize, pos.1 as usize);
-                        self.grid.set(pos.0, pos.1, 0);
-                        self.grid_changes.push((pos.0 as u32, pos.1 as u32, 0));
-                    }
-                }
+                let point = Vec2::new(xi as f32, yi as f32);
+                let damage = self.weapons.get(b.ty).damage;
+                damage_base(bases, &mut self.round, owner_team, point, damage);
+                let changes = self.weapons.get(b.ty).crater().blast(
+                    &mut self.grid,
+                    (xi as i32, yi as i32),
+                    solid_for(b.reality),
+                    air_of(b.reality),
+                    debris_of(b.reality),
+                );
+                self.grid_changes.extend(changes);
             } else {
                 b.position += b.direction;
+                b.age += 1;
+                // Bullets that leave the world or fly for too long would otherwise live forever
+                let (width, height) = self.grid.get_size();
+                let inside = b.position.x >= 0.0
+                    && b.position.y >= 0.0
+                    && b.position.x < width as f32
+                    && b.position.y < height as f32;
+                if !inside || b.age >= self.weapons.get(b.ty).lifetime {
+                    to_remove.push(idx);
+                    continue;
+                }
+                let damage = self.weapons.get(b.ty).damage;
+                if damage_base(bases, &mut self.round, owner_team, b.position, damage) {
+                    to_remove.push(idx);
+                    continue;
+                }
+                let hit = self.players.iter_mut().find(|player| {
+                    player.is_alive()
+                        && player.id != b.owner
+                        && Some(player.team) != owner_team
+                        && player.hitbox_contains(b.position)
+                });
+                let mut killed = false;
+                if let Some(player) = hit {
+                    to_remove.push(idx);
+                    player.health = player.health.saturating_sub(damage);
+                    if player.health == 0 {
+                        killed = true;
+                        player.deaths += 1;
+                        player.respawn_tick = Some(self.tick + RESPAWN_TICKS);
+                        self.removed.push((player.id, EntityType::Player));
+                        self.events.push(GameEvent::Died {
+                            id: player.id,
+                            killer: Some(b.owner),
+                        });
+                    }
+                }
+                if killed {
+                    if let Some(killer) = self.players.iter_mut().find(|x| x.id == b.owner) {
+                        killer.kills += 1;
+                    }
+                }
             }
         }
 
@@ -331,10 +1127,143 @@ impl ServerLogic {
     }
 }
 
+/// Input of a single client tick that hasn't been applied yet
+#[derive(Debug)]
+pub struct QueuedInput {
+    pub sequence: u32,
+    pub commands: Vec<InputCommand>,
+    pub aim: Option<f32>,
+    /// Weapon the client switched to after sending this input
+    pub switch: Option<Weapon>,
+}
+
 #[derive(Debug)]
 pub struct ServerPlayer {
     inner: PlayerData,
-    pub input: winput::Input,
+    pub actions: ActionState,
+    /// Inputs received but not applied yet, oldest first
+    pub inputs: VecDeque<QueuedInput>,
+    /// Weapon switch that followed the last applied input, made before the next input is
+    /// applied so the shots of that input are fired with the previous weapon
+    pub weapon_switch: Option<Weapon>,
+    /// Sequence number of the last input message applied to this player
+    pub last_input_sequence: u32,
+    /// Tick at which a dead player respawns, `None` while alive
+    pub respawn_tick: Option<u64>,
+    /// Players killed in the current match
+    pub kills: u32,
+    /// Times the player died in the current match
+    pub deaths: u32,
+}
+
+impl ServerPlayer {
+    pub fn is_alive(&self) -> bool {
+        self.respawn_tick.is_none()
+    }
+
+    fn hitbox_contains(&self, point: Vec2) -> bool {
+        let offset = point - self.position;
+        offset.x >= 0.0 && offset.x <= 10.0 && offset.y >= 0.0 && offset.y <= 10.0
+    }
+}
+
+/// Makes `player` hold `weapon`, see `ServerLogic::switch_weapon`
+fn hold_weapon(player: &mut ServerPlayer, weapon: Weapon, weapons: &WeaponRegistry) -> bool {
+    if !weapons.contains(weapon) || player.curr_weapon == weapon {
+        return false;
+    }
+    player.curr_weapon = weapon;
+    player.curr_weapon_cooldown = weapons.get(weapon).reload_time;
+    player.ammo = 0;
+    true
+}
+
+/// Cells overlapped by the hitbox of a player at `position`, some may be outside the grid
+fn hitbox_cells(position: Vec2) -> impl Iterator<Item = (usize, usize)> {
+    let start = (position.x.max(0.0) as usize, position.y.max(0.0) as usize);
+    let end = (
+        (position.x + 9.99).max(0.0) as usize,
+        (position.y + 9.99).max(0.0) as usize,
+    );
+    (start.0..=end.0).flat_map(move |i| (start.1..=end.1).map(move |j| (i, j)))
+}
+
+/// Damages the enemy base whose core contains `point`, while the round is running. Returns
+/// true if a base was hit.
+fn damage_base(
+    bases: &[Base],
+    round: &mut RoundStatus,
+    attacker: Option<Team>,
+    point: Vec2,
+    damage: u32,
+) -> bool {
+    let attacker = match attacker {
+        Some(attacker) if round.is_running() => attacker,
+        _ => return false,
+    };
+    match bases
+        .iter()
+        .find(|base| base.team != attacker && base.core_contains(point))
+    {
+        Some(base) => {
+            let health = &mut round.base_health[base.team.index()];
+            *health = health.saturating_sub(damage);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Brings `player` back to life at the base of its team, or at a free position in the reality
+/// of its team if there are no bases
+fn respawn(
+    player: &mut ServerPlayer,
+    bases: &[Base],
+    grid: &Grid<Reality>,
+    random: &mut Pcg64Mcg,
+    events: &mut Vec<GameEvent>,
+) {
+    let team = player.team;
+    let position = match bases.iter().find(|base| base.team == team) {
+        Some(base) => base.spawn_point(),
+        None => find_spawn(grid, team.reality(), random).unwrap_or(player.position),
+    };
+    player.inner = PlayerData {
+        position,
+        curr_weapon: player.curr_weapon,
+        aim: player.aim,
+        team,
+        ..PlayerData::new(player.id, team.reality(), position)
+    };
+    player.respawn_tick = None;
+    events.push(GameEvent::Respawned {
+        id: player.id,
+        position,
+    });
+}
+
+/// A random position where a player of `reality` fits without touching terrain
+fn find_spawn(grid: &Grid<Reality>, reality: u32, random: &mut Pcg64Mcg) -> Option<Vec2> {
+    let (width, height) = grid.get_size();
+    if width <= 10 || height <= 10 {
+        return None;
+    }
+    (0..SPAWN_ATTEMPTS)
+        .map(|_| {
+            (
+                random.gen_range(0, width - 10),
+                random.gen_range(0, height - 10),
+            )
+        })
+        .find(|(x, y)| {
+            (*x..x + 10).all(|i| {
+                (*y..y + 10).all(|j| {
+                    grid.get(i, j)
+                        .map_or(false, |v| cell_reality(*v) == reality)
+                })
+            })
+        })
+        .map(|(x, y)| Vec2::new(x as f32, y as f32))
 }
 
 impl std::ops::Deref for ServerPlayer {
@@ -349,79 +1278,603 @@ impl std::ops::DerefMut for ServerPlayer {
     }
 }
 
-/// Returns true if collision happened on y axis
-fn check_for_collision_and_move_player_according_to_movement_vector(
-    grid: &Grid<Reality>,
-    player: &mut PlayerData,
-    _logger: &mut Logger<Log>,
-) -> bool {
-    let movement = player.velocity;
-    let tl = Vec2 {
-        x: player.position.x + 0.01,
-        y: player.position.y + 0.01,
-    };
-    let tr = Vec2 {
-        x: player.position.x + 9.99,
-        y: player.position.y + 0.01,
-    };
-    let bl = Vec2 {
-        x: player.position.x + 0.01,
-        y: player.position.y + 9.99,
-    };
-    let br = Vec2 {
-        x: player.position.x + 9.99,
-        y: player.position.y + 9.99,
-    };
-    let mut collision_y = None;
-    let ymove = Vec2 {
-        x: 0.0,
-        y: movement.y,
-    };
-    for i in 1..=10 {
-        collision_y = collision_test(&[tl, tr, br, bl], Some(0.5), ymove / i as f32, grid, |x| {
-            *x > 0
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_player_is_recorded() {
+        let mut logic = ServerLogic::default();
+        let first = logic.add_player();
+        let second = logic.add_player();
+
+        logic.remove_player(first);
+
+        assert_eq![1, logic.players.len()];
+        assert_eq![second, logic.players[0].id];
+        assert_eq![1, logic.removed.len()];
+        assert_eq![first, logic.removed[0].0];
+    }
+
+    #[test]
+    fn removing_unknown_player_does_nothing() {
+        let mut logic = ServerLogic::default();
+        logic.add_player();
+
+        logic.remove_player(123);
+
+        assert_eq![1, logic.players.len()];
+        assert![logic.removed.is_empty()];
+    }
+
+    #[test]
+    fn weapon_switch_is_validated() {
+        let mut logic = ServerLogic::default();
+        let id = logic.add_player();
+
+        assert![!logic.switch_weapon(id, Weapon(0))];
+        assert![!logic.switch_weapon(id + 1, Weapon(1))];
+        assert![!logic.switch_weapon(id, Weapon(200))];
+        assert![logic.switch_weapon(id, Weapon(1))];
+        assert_eq![Weapon(1), logic.players[0].curr_weapon];
+        assert_eq![0, logic.players[0].ammo];
+    }
+
+    #[test]
+    fn fire_rate_and_magazine_are_enforced() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        logic.add_player();
+        logic.players[0].actions.set(Action::Fire, true);
+        let stats = logic.weapons.get(logic.players[0].curr_weapon).clone();
+        let magazine = stats.magazine_size as usize;
+
+        for _ in 0..(magazine - 1) * stats.fire_interval + 1 {
+            logic.update_players(&mut random);
+        }
+        assert_eq![magazine * stats.bullet_count as usize, logic.bullets.len()];
+        assert_eq![0, logic.players[0].ammo];
+
+        for _ in 0..stats.reload_time - 1 {
+            logic.update_players(&mut random);
+        }
+        assert_eq![magazine * stats.bullet_count as usize, logic.bullets.len()];
+
+        logic.update_players(&mut random);
+        assert_eq![stats.magazine_size - 1, logic.players[0].ammo];
+    }
+
+    /// A logic with an empty 100x100 world and two players standing next to each other
+    fn logic_with_two_players() -> (ServerLogic, Id, Id) {
+        let mut logic = ServerLogic::default();
+        logic.grid.resize(100, 100);
+        let shooter = logic.add_player();
+        let target = logic.add_player();
+        logic.players[1].position = Vec2::new(20.0, 0.0);
+        (logic, shooter, target)
+    }
+
+    fn shoot(logic: &mut ServerLogic, owner: Id, from: Vec2) {
+        logic.bullets.push(Bullet {
+            direction: Vec2::new(1.0, 0.0),
+            position: from,
+            owner,
+            ..Bullet::default()
         });
-        if collision_y.is_none() {
-            player.position += ymove / i as f32;
-            break;
+        for _ in 0..20 {
+            logic.update_bullets();
         }
     }
 
-    let tl = Vec2 {
-        x: player.position.x + 0.01,
-        y: player.position.y + 0.01,
-    };
-    let tr = Vec2 {
-        x: player.position.x + 9.99,
-        y: player.position.y + 0.01,
-    };
-    let bl = Vec2 {
-        x: player.position.x + 0.01,
-        y: player.position.y + 9.99,
-    };
-    let br = Vec2 {
-        x: player.position.x + 9.99,
-        y: player.position.y + 9.99,
-    };
-    let mut collision_x = None;
-    let xmove = Vec2 {
-        x: movement.x,
-        y: 0.0,
-    };
-    for i in 1..=10 {
-        collision_x = collision_test(&[tl, tr, br, bl], Some(0.5), xmove / i as f32, grid, |x| {
-            *x > 0
+    #[test]
+    fn bullets_damage_other_players() {
+        let (mut logic, shooter, _) = logic_with_two_players();
+        let damage = logic.weapons.get(Weapon::default()).damage;
+
+        shoot(&mut logic, shooter, Vec2::new(5.0, 5.0));
+
+        assert![logic.bullets.is_empty()];
+        assert_eq![MAX_HEALTH, logic.players[0].health];
+        assert_eq![MAX_HEALTH - damage, logic.players[1].health];
+        assert![logic.events.is_empty()];
+    }
+
+    #[test]
+    fn dead_players_respawn_after_a_delay() {
+        let (mut logic, shooter, target) = logic_with_two_players();
+        let mut random = Pcg64Mcg::new(0);
+        logic.players[1].health = 1;
+
+        shoot(&mut logic, shooter, Vec2::new(5.0, 5.0));
+
+        assert![!logic.players[1].is_alive()];
+        assert![logic.removed.contains(&(target, EntityType::Player))];
+        assert_eq![
+            vec![GameEvent::Died {
+                id: target,
+                killer: Some(shooter)
+            }],
+            logic.events
+        ];
+
+        logic.events.clear();
+        logic.tick += RESPAWN_TICKS - 1;
+        logic.update_respawns(&mut random);
+        assert![!logic.players[1].is_alive()];
+
+        logic.tick += 1;
+        logic.update_respawns(&mut random);
+        assert![logic.players[1].is_alive()];
+        assert_eq![MAX_HEALTH, logic.players[1].health];
+        assert_eq![
+            vec![GameEvent::Respawned {
+                id: target,
+                position: logic.players[1].position
+            }],
+            logic.events
+        ];
+    }
+
+    #[test]
+    fn spawn_is_free_of_terrain() {
+        let mut grid = Grid::new();
+        grid.resize(100, 100);
+        for i in 0..100 {
+            for j in 0..100 {
+                if i < 50 || j < 50 {
+                    grid.set(i, j, 1);
+                }
+            }
+        }
+        let mut random = Pcg64Mcg::new(0);
+        for _ in 0..10 {
+            if let Some(spawn) = find_spawn(&grid, BLACK_REALITY, &mut random) {
+                assert![spawn.x >= 50.0 && spawn.y >= 50.0];
+            }
+            if let Some(spawn) = find_spawn(&grid, WHITE_REALITY, &mut random) {
+                assert![spawn.x <= 40.0 || spawn.y <= 40.0];
+            }
+        }
+        let mut full = Grid::new();
+        full.resize(100, 100);
+        for i in 0..100 {
+            for j in 0..100 {
+                full.set(i, j, 1);
+            }
+        }
+        assert_eq![None, find_spawn(&full, BLACK_REALITY, &mut random)];
+    }
+
+    /// A 100x100 black world with a white wall from x = 30 to 39, and a bullet of `reality`
+    /// flying right from `x`
+    fn bullet_towards_wall(reality: u32, x: f32) -> ServerLogic {
+        let mut logic = ServerLogic::default();
+        logic.grid.resize(100, 100);
+        for i in 30..40 {
+            for j in 0..100 {
+                logic.grid.set(i, j, 200);
+            }
+        }
+        logic.bullets.push(Bullet {
+            direction: Vec2::new(1.0, 0.0),
+            position: Vec2::new(x, 50.5),
+            reality,
+            ..Bullet::default()
+        });
+        for _ in 0..30 {
+            logic.update_bullets();
+        }
+        logic
+    }
+
+    /// A logic with an empty world of the given size and a bullet flying right from `x`
+    fn flying_bullet(width: usize, x: f32) -> ServerLogic {
+        let mut logic = ServerLogic::default();
+        logic.grid.resize(width, 10);
+        logic.bullets.push(Bullet {
+            direction: Vec2::new(1.0, 0.0),
+            position: Vec2::new(x, 5.5),
+            id: 3,
+            ..Bullet::default()
+        });
+        logic
+    }
+
+    #[test]
+    fn bullets_expire_after_their_lifetime() {
+        let mut logic = flying_bullet(1000, 0.5);
+        let lifetime = logic.weapons.get(Weapon::default()).lifetime;
+        for _ in 0..lifetime - 1 {
+            logic.update_bullets();
+        }
+        assert_eq![1, logic.bullets.len()];
+
+        logic.update_bullets();
+        assert![logic.bullets.is_empty()];
+        assert_eq![vec![(3, EntityType::Bullet)], logic.removed];
+    }
+
+    #[test]
+    fn bullets_leaving_the_world_are_removed() {
+        let mut logic = flying_bullet(20, 18.5);
+        logic.update_bullets();
+        assert_eq![1, logic.bullets.len()];
+
+        logic.update_bullets();
+        assert![logic.bullets.is_empty()];
+        assert_eq![vec![(3, EntityType::Bullet)], logic.removed];
+    }
+
+    #[test]
+    fn bullets_turn_terrain_into_their_reality() {
+        let black = bullet_towards_wall(BLACK_REALITY, 5.5);
+        assert![black.bullets.is_empty()];
+        assert_eq![Some(&0), black.grid.get(30, 50)];
+        assert![black.grid_changes.iter().all(|(_, _, cell)| *cell == 0)];
+
+        let white = bullet_towards_wall(WHITE_REALITY, 31.5);
+        assert![white.bullets.is_empty()];
+        assert_eq![Some(&200), white.grid.get(35, 50)];
+        assert_eq![Some(&air_of(WHITE_REALITY)), white.grid.get(40, 50)];
+    }
+
+    #[test]
+    fn craters_at_the_edge_stay_in_the_grid() {
+        let mut logic = ServerLogic::default();
+        logic.grid.resize(20, 20);
+        for i in 0..20 {
+            logic.grid.set(i, 0, 200);
+        }
+        logic.bullets.push(Bullet {
+            direction: Vec2::new(0.0, -1.0),
+            position: Vec2::new(1.5, 2.5),
+            ..Bullet::default()
         });
-        if collision_x.is_none() {
-            player.position += xmove / i as f32;
-            break;
+        for _ in 0..5 {
+            logic.update_bullets();
+        }
+        assert![logic.bullets.is_empty()];
+        // Cells of the top row within the radius of the impact at (1, 0), clipped at x = 0
+        assert_eq![5, logic.grid_changes.len()];
+        assert![logic
+            .grid_changes
+            .iter()
+            .all(|(x, y, _)| *x < 20 && *y < 20)];
+    }
+
+    #[test]
+    fn switching_reality_clears_space_around_player() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        logic.grid.resize(100, 100);
+        let id = logic.add_player();
+        logic.players[0].position = Vec2::new(20.0, 20.0);
+
+        logic.handle_input(
+            id,
+            1,
+            vec![InputCommand::Pressed(Action::SwitchReality)],
+            None,
+        );
+        logic.update_inputs(&mut random, &mut Logger::spawn_void());
+
+        assert_eq![WHITE_REALITY, logic.players[0].reality];
+        assert![hitbox_cells(Vec2::new(20.0, 20.0))
+            .all(|(x, y)| logic.grid.get(x, y) == Some(&air_of(WHITE_REALITY)))];
+        assert_eq![100, logic.grid_changes.len()];
+    }
+
+    #[test]
+    fn inputs_are_applied_once_per_tick_in_order() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        let mut logger = Logger::spawn_void();
+        let id = logic.add_player();
+
+        assert![logic.handle_input(id, 1, vec![], None)];
+        assert![logic.handle_input(id, 2, vec![], None)];
+        // Resent and outdated inputs are dropped
+        assert![!logic.handle_input(id, 2, vec![], None)];
+        assert![!logic.handle_input(id, 1, vec![], None)];
+        assert![!logic.handle_input(id + 1, 3, vec![], None)];
+
+        logic.update_inputs(&mut random, &mut logger);
+        assert_eq![1, logic.players[0].last_input_sequence];
+        logic.update_inputs(&mut random, &mut logger);
+        logic.update_inputs(&mut random, &mut logger);
+        assert_eq![2, logic.players[0].last_input_sequence];
+        assert![!logic.handle_input(id, 2, vec![], None)];
+    }
+
+    #[test]
+    fn weapon_switch_waits_for_earlier_inputs() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        let mut logger = Logger::spawn_void();
+        let id = logic.add_player();
+
+        assert![logic.handle_input(id, 1, vec![], None)];
+        assert![logic.handle_input(id, 2, vec![], None)];
+        assert![logic.handle_weapon_switch(id, Weapon(1))];
+        assert![!logic.handle_weapon_switch(id, Weapon(200))];
+
+        logic.update_inputs(&mut random, &mut logger);
+        logic.update_inputs(&mut random, &mut logger);
+        assert_eq![Weapon(0), logic.players[0].curr_weapon];
+        logic.update_inputs(&mut random, &mut logger);
+        assert_eq![Weapon(1), logic.players[0].curr_weapon];
+    }
+
+    #[test]
+    fn flooding_inputs_merges_their_commands() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        let mut logger = Logger::spawn_void();
+        let id = logic.add_player();
+
+        logic.handle_input(id, 1, vec![InputCommand::Pressed(Action::Walk)], None);
+        for sequence in 2..=MAX_QUEUED_INPUTS as u32 + 10 {
+            logic.handle_input(id, sequence, vec![], None);
+        }
+        assert_eq![MAX_QUEUED_INPUTS, logic.players[0].inputs.len()];
+
+        logic.update_inputs(&mut random, &mut logger);
+        assert![logic.players[0].actions.is_down(Action::Walk)];
+        assert_eq![11, logic.players[0].last_input_sequence];
+    }
+
+    #[test]
+    fn player_painted_over_joins_other_reality() {
+        let mut logic = ServerLogic::default();
+        let mut random = Pcg64Mcg::new(0);
+        logic.grid.resize(100, 100);
+        logic.add_player();
+        logic.players[0].position = Vec2::new(20.0, 20.0);
+
+        logic.update_players(&mut random);
+        assert_eq![BLACK_REALITY, logic.players[0].reality];
+
+        for (x, y) in hitbox_cells(Vec2::new(20.0, 20.0)) {
+            logic.grid.set(x, y, 255);
+        }
+        logic.update_players(&mut random);
+        assert_eq![WHITE_REALITY, logic.players[0].reality];
+    }
+
+    /// A running match in an empty 200x100 world with carved bases and a player in each team
+    fn team_logic() -> ServerLogic {
+        let mut logic = ServerLogic::default();
+        logic.match_state = MatchState::Running;
+        logic.grid.resize(200, 100);
+        logic.bases = Base::for_world(200, 100).to_vec();
+        for base in &logic.bases {
+            base.carve(&mut logic.grid);
+        }
+        logic.add_player();
+        logic.add_player();
+        logic
+    }
+
+    #[test]
+    fn players_join_the_smaller_team_at_its_base() {
+        let mut logic = team_logic();
+        logic.add_player();
+        let teams: Vec<Team> = logic.players.iter().map(|player| player.team).collect();
+        assert_eq![vec![Team::Black, Team::White, Team::Black], teams];
+        for player in &logic.players {
+            let base = logic.bases.iter().find(|base| base.team == player.team);
+            assert_eq![base.unwrap().spawn_point(), player.position];
+            assert_eq![player.team.reality(), player.reality];
+        }
+    }
+
+    #[test]
+    fn bullets_do_not_hurt_teammates() {
+        let (mut logic, shooter, _) = logic_with_two_players();
+        logic.players[1].team = Team::Black;
+
+        shoot(&mut logic, shooter, Vec2::new(5.0, 5.0));
+
+        assert_eq![MAX_HEALTH, logic.players[1].health];
+    }
+
+    #[test]
+    fn enemy_bullets_damage_base_core() {
+        let mut logic = team_logic();
+        let damage = logic.weapons.get(Weapon::default()).damage;
+        let (black, white) = (logic.players[0].id, logic.players[1].id);
+        let white_core = logic.bases[1].position;
+
+        shoot(&mut logic, white, white_core - Vec2::new(3.0, 0.0));
+        assert_eq![[BASE_HEALTH; 2], logic.round.base_health];
+
+        shoot(&mut logic, black, white_core - Vec2::new(3.0, 0.0));
+        assert_eq![[BASE_HEALTH, BASE_HEALTH - damage], logic.round.base_health];
+    }
+
+    #[test]
+    fn destroyed_base_ends_round_until_restart() {
+        let mut logic = team_logic();
+        let mut random = Pcg64Mcg::new(0);
+        logic.round.base_health[Team::White.index()] = 0;
+        logic.players[0].position = Vec2::new(0.0, 0.0);
+        logic.grid.set(50, 50, 100);
+
+        logic.update_round(&mut random);
+        assert_eq![
+            RoundState::Ended {
+                winner: Team::Black,
+                reason: RoundEnd::BaseDestroyed
+            },
+            logic.round.state
+        ];
+        assert_eq![[1, 0], logic.round.scores];
+
+        logic.tick += ROUND_RESTART_TICKS;
+        logic.update_round(&mut random);
+        assert![logic.round.is_running()];
+        assert_eq![1, logic.round.number];
+        assert_eq![[BASE_HEALTH; 2], logic.round.base_health];
+        assert_eq![logic.bases[0].spawn_point(), logic.players[0].position];
+        assert_eq![Some(&0), logic.grid.get(50, 50)];
+        assert_eq![vec![(50, 50, 0)], logic.grid_changes];
+    }
+
+    #[test]
+    fn undefended_base_is_captured() {
+        let mut logic = team_logic();
+        let mut random = Pcg64Mcg::new(0);
+        logic.players[0].position = logic.bases[1].spawn_point();
+
+        logic.update_round(&mut random);
+        assert_eq![[0, 0], logic.round.capture_progress];
+
+        logic.players[1].position = Vec2::new(0.0, 0.0);
+        for _ in 0..CAPTURE_TICKS - 1 {
+            logic.update_round(&mut random);
         }
+        assert![logic.round.is_running()];
+        assert_eq![CAPTURE_TICKS - 1, logic.round.capture_progress[1]];
+
+        logic.update_round(&mut random);
+        assert_eq![
+            RoundState::Ended {
+                winner: Team::Black,
+                reason: RoundEnd::BaseCaptured
+            },
+            logic.round.state
+        ];
     }
-    if collision_x.is_some() {
-        player.velocity.x = 0.0;
+
+    fn run_match(logic: &mut ServerLogic, config: &ServerConfig, ticks: u64) {
+        let mut random = Pcg64Mcg::new(0);
+        for _ in 0..ticks {
+            logic.tick += 1;
+            logic.update_match(config, &mut random);
+        }
     }
-    if collision_y.is_some() {
-        player.velocity.y = 0.0;
+
+    #[test]
+    fn warmup_waits_for_both_teams() {
+        let mut logic = ServerLogic::default();
+        let config = ServerConfig {
+            warmup_time: 1.0,
+            ..ServerConfig::default()
+        };
+        logic.add_player();
+        run_match(&mut logic, &config, 200);
+        assert_eq![MatchState::Warmup, logic.match_state];
+
+        logic.add_player();
+        logic.players[0].kills = 2;
+        run_match(&mut logic, &config, 124);
+        assert_eq![MatchState::Warmup, logic.match_state];
+        run_match(&mut logic, &config, 1);
+        assert_eq![MatchState::Running, logic.match_state];
+        assert_eq![0, logic.players[0].kills];
+    }
+
+    #[test]
+    fn score_limit_ends_match_and_new_world_follows() {
+        let mut logic = team_logic();
+        let config = ServerConfig {
+            score_limit: 2,
+            restart_delay: 1.0,
+            ..ServerConfig::default()
+        };
+        let seed = logic.world_seed;
+        logic.round.scores = [2, 1];
+        logic.players[1].deaths = 4;
+
+        run_match(&mut logic, &config, 1);
+        assert_eq![
+            MatchState::Ended {
+                winner: Some(Team::Black)
+            },
+            logic.match_state
+        ];
+        assert_eq![[2, 1], logic.scoreboard().team_scores];
+
+        run_match(&mut logic, &config, 125);
+        assert_eq![MatchState::Warmup, logic.match_state];
+        assert![logic.new_world];
+        assert_ne![seed, logic.world_seed];
+        assert_eq![1, logic.world];
+        assert_eq![(200, 100), logic.grid.get_size()];
+        assert_eq![[0, 0], logic.round.scores];
+        assert_eq![0, logic.players[1].deaths];
+    }
+
+    #[test]
+    fn time_limit_ends_match_in_a_draw() {
+        let mut logic = team_logic();
+        let config = ServerConfig {
+            time_limit: 1.0,
+            ..ServerConfig::default()
+        };
+        logic.round.scores = [1, 1];
+        run_match(&mut logic, &config, 124);
+        assert_eq![MatchState::Running, logic.match_state];
+        run_match(&mut logic, &config, 1);
+        assert_eq![MatchState::Ended { winner: None }, logic.match_state];
+    }
+
+    #[test]
+    fn kills_and_deaths_are_counted() {
+        let (mut logic, shooter, target) = logic_with_two_players();
+        logic.players[1].health = 1;
+
+        shoot(&mut logic, shooter, Vec2::new(5.0, 5.0));
+
+        let scoreboard = logic.scoreboard();
+        let score = |id| scoreboard.players.iter().find(|x| x.id == id).unwrap();
+        assert_eq![(1, 0), (score(shooter).kills, score(shooter).deaths)];
+        assert_eq![(0, 1), (score(target).kills, score(target).deaths)];
+    }
+
+    #[test]
+    fn acknowledged_snapshot_becomes_baseline() {
+        let mut client = RemoteClient::default();
+        assert![client.baseline().is_none()];
+        for number in 1..=3 {
+            client
+                .sent_snapshots
+                .push_back((number, Snapshot::default()));
+        }
+
+        client.acknowledge(2);
+        client.acknowledge(1);
+
+        assert_eq![Some(2), client.baseline().map(|(number, _)| number)];
+        assert_eq![2, client.sent_snapshots.len()];
+    }
+
+    #[test]
+    fn entities_outside_region_are_filtered() {
+        let snapshot = Snapshot {
+            players: vec![
+                PlayerData::new(0, 0, Vec2::new(500.0, 500.0)),
+                PlayerData::new(1, 0, Vec2::new(10.0, 10.0)),
+                PlayerData::new(2, 0, Vec2::new(900.0, 900.0)),
+            ],
+            bullets: vec![Bullet {
+                position: Vec2::new(900.0, 900.0),
+                ..Bullet::default()
+            }],
+        };
+        let region = Bocs {
+            start: Vec2::new(0.0, 0.0),
+            difference: Vec2::new(100.0, 100.0),
+        };
+
+        let filtered = filter_snapshot(&snapshot, Some(region), 0);
+
+        let ids: Vec<Id> = filtered.players.iter().map(|x| x.id).collect();
+        assert_eq![vec![0, 1], ids];
+        assert![filtered.bullets.is_empty()];
+        assert_eq![
+            vec![(2, EntityType::Player), (0, EntityType::Bullet)],
+            left_region(&snapshot, &filtered)
+        ];
     }
-    collision_y.is_some()
 }
@@ -0,0 +1,283 @@
+use crate::game::{Bullet, Id, PlayerData};
+use failure::{format_err, Error};
+use std::collections::{HashMap, HashSet};
+
+/// The replicated state of the game at a single server tick
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub players: Vec<PlayerData>,
+    pub bullets: Vec<Bullet>,
+}
+
+/// Entities that can be encoded field by field against a baseline.
+///
+/// Every field has a bit in a mask, only fields whose bit is set are written.
+pub trait DeltaEncode: Clone + Default {
+    /// Mask with the bit of every field set
+    const ALL_FIELDS: u16;
+    fn id(&self) -> Id;
+    /// Mask of the fields that differ from `baseline`
+    fn changed_fields(&self, baseline: &Self) -> u16;
+    fn write_fields(&self, mask: u16, out: &mut Vec<u8>) -> Result<(), Error>;
+    fn read_fields(&mut self, mask: u16, input: &mut &[u8]) -> Result<(), Error>;
+}
+
+/// Implements `DeltaEncode` given the bit assigned to each field.
+/// Bits must stay below 16 and must never be reused for a different field.
+macro_rules! impl_delta_encode {
+    ($ty:ty { $($field:ident = $bit:expr),* $(,)* }) => {
+        impl DeltaEncode for $ty {
+            const ALL_FIELDS: u16 = 0 $(| 1 << $bit)*;
+            fn id(&self) -> Id {
+                self.id
+            }
+            fn changed_fields(&self, baseline: &Self) -> u16 {
+                let mut mask = 0;
+                $(
+                    if self.$field != baseline.$field {
+                        mask |= 1 << $bit;
+                    }
+                )*
+                mask
+            }
+            fn write_fields(&self, mask: u16, out: &mut Vec<u8>) -> Result<(), Error> {
+                $(
+                    if mask & 1 << $bit != 0 {
+                        bincode::serialize_into(&mut *out, &self.$field)?;
+                    }
+                )*
+                Ok(())
+            }
+            fn read_fields(&mut self, mask: u16, input: &mut &[u8]) -> Result<(), Error> {
+                $(
+                    if mask & 1 << $bit != 0 {
+                        self.$field = bincode::deserialize_from(&mut *input)?;
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_delta_encode!(PlayerData {
+    position = 0,
+    velocity = 1,
+    id = 2,
+    curr_weapon = 3,
+    curr_weapon_cooldown = 4,
+    reality = 5,
+    aim = 6,
+    ammo = 7,
+    health = 8,
+    team = 9,
+});
+
+impl_delta_encode!(Bullet {
+    direction = 0,
+    position = 1,
+    id = 2,
+    ty = 3,
+    owner = 4,
+    reality = 5,
+});
+
+/// Encodes `current` as the changes relative to `baseline`, or in full if there is no baseline.
+pub fn encode_snapshot(current: &Snapshot, baseline: Option<&Snapshot>) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    match baseline {
+        Some(baseline) => {
+            encode_entities(&current.players, &baseline.players, &mut out)?;
+            encode_entities(&current.bullets, &baseline.bullets, &mut out)?;
+        }
+        None => {
+            encode_entities(&current.players, &[], &mut out)?;
+            encode_entities(&current.bullets, &[], &mut out)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Reconstructs a snapshot from data produced by `encode_snapshot` with the same baseline.
+pub fn decode_snapshot(data: &[u8], baseline: Option<&Snapshot>) -> Result<Snapshot, Error> {
+    let mut input = data;
+    let snapshot = match baseline {
+        Some(baseline) => Snapshot {
+            players: decode_entities(&mut input, &baseline.players)?,
+            bullets: decode_entities(&mut input, &baseline.bullets)?,
+        },
+        None => Snapshot {
+            players: decode_entities(&mut input, &[])?,
+            bullets: decode_entities(&mut input, &[])?,
+        },
+    };
+    if !input.is_empty() {
+        return Err(format_err!["{} trailing bytes in snapshot", input.len()]);
+    }
+    Ok(snapshot)
+}
+
+/// Layout: ids removed since the baseline, number of changed entities, then for each changed
+/// entity its id, field mask and the fields in the mask. Unchanged entities are omitted.
+fn encode_entities<T: DeltaEncode>(
+    current: &[T],
+    baseline: &[T],
+    out: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let baseline: HashMap<Id, &T> = baseline.iter().map(|x| (x.id(), x)).collect();
+    let present: HashSet<Id> = current.iter().map(DeltaEncode::id).collect();
+
+    let removed: Vec<Id> = baseline
+        .keys()
+        .filter(|id| !present.contains(id))
+        .cloned()
+        .collect();
+
+    let changes: Vec<(&T, u16)> = current
+        .iter()
+        .filter_map(|entity| {
+            let mask = match baseline.get(&entity.id()) {
+                Some(base) => entity.changed_fields(base),
+                None => T::ALL_FIELDS,
+            };
+            if mask != 0 {
+                Some((entity, mask))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    bincode::serialize_into(&mut *out, &removed)?;
+    bincode::serialize_into(&mut *out, &(changes.len() as u32))?;
+    for (entity, mask) in changes {
+        bincode::serialize_into(&mut *out, &entity.id())?;
+        bincode::serialize_into(&mut *out, &mask)?;
+        entity.write_fields(mask, out)?;
+    }
+    Ok(())
+}
+
+fn decode_entities<T: DeltaEncode>(input: &mut &[u8], baseline: &[T]) -> Result<Vec<T>, Error> {
+    let removed: Vec<Id> = bincode::deserialize_from(&mut *input)?;
+    let removed: HashSet<Id> = removed.into_iter().collect();
+    let mut entities: Vec<T> = baseline
+        .iter()
+        .filter(|x| !removed.contains(&x.id()))
+        .cloned()
+        .collect();
+
+    let count: u32 = bincode::deserialize_from(&mut *input)?;
+    for _ in 0..count {
+        let id: Id = bincode::deserialize_from(&mut *input)?;
+        let mask: u16 = bincode::deserialize_from(&mut *input)?;
+        match entities.iter_mut().find(|x| x.id() == id) {
+            Some(entity) => entity.read_fields(mask, input)?,
+            None => {
+                let mut entity = T::default();
+                entity.read_fields(mask, input)?;
+                entities.push(entity);
+            }
+        }
+    }
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::vec::Vec2;
+
+    fn player(id: Id, x: f32) -> PlayerData {
+        PlayerData::new(id, 0, Vec2::new(x, 0.0))
+    }
+
+    fn bullet(id: Id, x: f32) -> Bullet {
+        Bullet {
+            direction: Vec2::new(1.0, 0.0),
+            position: Vec2::new(x, 1.0),
+            id,
+            ..Bullet::default()
+        }
+    }
+
+    fn positions(snapshot: &Snapshot) -> (Vec<(Id, Vec2)>, Vec<(Id, Vec2)>) {
+        (
+            snapshot
+                .players
+                .iter()
+                .map(|x| (x.id, x.position))
+                .collect(),
+            snapshot
+                .bullets
+                .iter()
+                .map(|x| (x.id, x.position))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn full_snapshot_roundtrip() {
+        let snapshot = Snapshot {
+            players: vec![player(0, 1.0), player(1, 2.0)],
+            bullets: vec![bullet(5, 3.0)],
+        };
+        let data = encode_snapshot(&snapshot, None).unwrap();
+        let decoded = decode_snapshot(&data, None).unwrap();
+        assert_eq![positions(&snapshot), positions(&decoded)];
+    }
+
+    #[test]
+    fn delta_snapshot_roundtrip() {
+        let baseline = Snapshot {
+            players: vec![player(0, 1.0), player(1, 2.0)],
+            bullets: vec![bullet(5, 3.0), bullet(6, 4.0)],
+        };
+        let current = Snapshot {
+            players: vec![player(0, 1.5), player(1, 2.0), player(2, 9.0)],
+            bullets: vec![bullet(6, 4.0)],
+        };
+        let data = encode_snapshot(&current, Some(&baseline)).unwrap();
+        let decoded = decode_snapshot(&data, Some(&baseline)).unwrap();
+        assert_eq![positions(&current), positions(&decoded)];
+    }
+
+    #[test]
+    fn unchanged_entities_are_omitted() {
+        let baseline = Snapshot {
+            players: (0..10).map(|id| player(id, id as f32)).collect(),
+            bullets: (0..100).map(|id| bullet(id, id as f32)).collect(),
+        };
+        let full = encode_snapshot(&baseline, None).unwrap();
+        let delta = encode_snapshot(&baseline, Some(&baseline)).unwrap();
+        assert![delta.len() < full.len() / 10];
+        let decoded = decode_snapshot(&delta, Some(&baseline)).unwrap();
+        assert_eq![positions(&baseline), positions(&decoded)];
+    }
+
+    #[test]
+    fn only_changed_fields_are_sent() {
+        let baseline = Snapshot {
+            players: vec![player(0, 1.0)],
+            bullets: vec![],
+        };
+        let mut moved = baseline.clone();
+        moved.players[0].position.x = 2.0;
+        let mut accelerated = moved.clone();
+        accelerated.players[0].velocity.x = 2.0;
+
+        let one_field = encode_snapshot(&moved, Some(&baseline)).unwrap();
+        let two_fields = encode_snapshot(&accelerated, Some(&baseline)).unwrap();
+        assert![one_field.len() < two_fields.len()];
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let snapshot = Snapshot {
+            players: vec![player(0, 1.0)],
+            bullets: vec![bullet(1, 1.0)],
+        };
+        let data = encode_snapshot(&snapshot, None).unwrap();
+        assert![decode_snapshot(&data[..data.len() - 1], None).is_err()];
+    }
+}
@@ -0,0 +1,254 @@
+use crate::game::{air_of, Reality, BLACK_REALITY, WHITE_REALITY};
+use geometry::{grid2d::Grid, vec::Vec2};
+
+/// Side length in cells of the square of air carved around a base
+pub const BASE_SIZE: usize = 60;
+/// Side length in cells of the square in the middle of a base that enemies attack
+pub const BASE_CORE_SIZE: f32 = 10.0;
+/// Health of a base at the start of a round
+pub const BASE_HEALTH: u32 = 1000;
+/// Ticks enemies must hold an undefended base core to capture it, 5 seconds
+pub const CAPTURE_TICKS: u32 = 625;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Team {
+    Black,
+    White,
+}
+
+impl Default for Team {
+    fn default() -> Team {
+        Team::Black
+    }
+}
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Black, Team::White];
+
+    /// Position of the team in per-team arrays like `RoundStatus::scores`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Reality the players of the team spawn in, which is also the air of their base
+    pub fn reality(self) -> u32 {
+        match self {
+            Team::Black => BLACK_REALITY,
+            Team::White => WHITE_REALITY,
+        }
+    }
+
+    pub fn opponent(self) -> Team {
+        match self {
+            Team::Black => Team::White,
+            Team::White => Team::Black,
+        }
+    }
+}
+
+/// The base of a team, where its players spawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Base {
+    pub team: Team,
+    /// Center of the base
+    pub position: Vec2,
+}
+
+impl Base {
+    /// The bases of both teams in a world of the given size, black on the left and white on
+    /// the right
+    pub fn for_world(width: usize, height: usize) -> [Base; 2] {
+        let y = height as f32 / 2.0;
+        [
+            Base {
+                team: Team::Black,
+                position: Vec2::new(width as f32 / 4.0, y),
+            },
+            Base {
+                team: Team::White,
+                position: Vec2::new(width as f32 * 3.0 / 4.0, y),
+            },
+        ]
+    }
+
+    /// Top left corner of a player standing in the middle of the base
+    pub fn spawn_point(&self) -> Vec2 {
+        self.position - Vec2::new(5.0, 5.0)
+    }
+
+    pub fn core_contains(&self, point: Vec2) -> bool {
+        let offset = point - self.position;
+        offset.x.abs() <= BASE_CORE_SIZE / 2.0 && offset.y.abs() <= BASE_CORE_SIZE / 2.0
+    }
+
+    pub fn area_contains(&self, point: Vec2) -> bool {
+        let offset = point - self.position;
+        let half = BASE_SIZE as f32 / 2.0;
+        offset.x.abs() <= half && offset.y.abs() <= half
+    }
+
+    /// Turns the area of the base into air of its team. Returns the cells that changed.
+    pub fn carve(&self, grid: &mut Grid<Reality>) -> Vec<(u32, u32, Reality)> {
+        let air = air_of(self.team.reality());
+        let (width, height) = grid.get_size();
+        let range = |center: f32, size: usize| {
+            let start = center as i64 - BASE_SIZE as i64 / 2;
+            start.max(0) as usize..(start + BASE_SIZE as i64).max(0).min(size as i64) as usize
+        };
+        let mut changes = Vec::new();
+        for i in range(self.position.x, width) {
+            for j in range(self.position.y, height) {
+                if grid.get(i, j) != Some(&air) {
+                    grid.set(i, j, air);
+                    changes.push((i as u32, j as u32, air));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Why a round ended
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum RoundEnd {
+    BaseDestroyed,
+    BaseCaptured,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum RoundState {
+    Running,
+    /// The round is over, a new one starts shortly
+    Ended {
+        winner: Team,
+        reason: RoundEnd,
+    },
+}
+
+impl Default for RoundState {
+    fn default() -> RoundState {
+        RoundState::Running
+    }
+}
+
+/// State of the current round, replicated to clients whenever it changes.
+/// Arrays are indexed by `Team::index`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RoundStatus {
+    /// Number of rounds started before this one
+    pub number: u32,
+    pub state: RoundState,
+    /// Rounds won by each team
+    pub scores: [u32; 2],
+    pub base_health: [u32; 2],
+    /// Ticks the enemies of each team have held its base core
+    pub capture_progress: [u32; 2],
+}
+
+impl Default for RoundStatus {
+    fn default() -> RoundStatus {
+        RoundStatus {
+            number: 0,
+            state: RoundState::Running,
+            scores: [0; 2],
+            base_health: [BASE_HEALTH; 2],
+            capture_progress: [0; 2],
+        }
+    }
+}
+
+impl RoundStatus {
+    pub fn is_running(&self) -> bool {
+        self.state == RoundState::Running
+    }
+
+    /// Ends the round in favour of `winner`
+    pub fn end(&mut self, winner: Team, reason: RoundEnd) {
+        self.state = RoundState::Ended { winner, reason };
+        self.scores[winner.index()] += 1;
+    }
+
+    /// The team that won more rounds, `None` on a tie
+    pub fn leader(&self) -> Option<Team> {
+        let (black, white) = (self.scores[0], self.scores[1]);
+        if black > white {
+            Some(Team::Black)
+        } else if white > black {
+            Some(Team::White)
+        } else {
+            None
+        }
+    }
+
+    /// Starts the next round with fresh bases, keeping the scores
+    pub fn restart(&mut self) {
+        *self = RoundStatus {
+            number: self.number + 1,
+            scores: self.scores,
+            ..RoundStatus::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carving_clears_base_area() {
+        let mut grid = Grid::new();
+        grid.resize(200, 100);
+        for i in 0..200 {
+            for j in 0..100 {
+                grid.set(i, j, 100);
+            }
+        }
+        let [black, white] = Base::for_world(200, 100);
+
+        let changes = black.carve(&mut grid);
+        assert_eq![BASE_SIZE * BASE_SIZE, changes.len()];
+        assert_eq![Some(&0), grid.get(50, 50)];
+        assert_eq![Some(&100), grid.get(150, 50)];
+        assert![black.carve(&mut grid).is_empty()];
+
+        // White air is any non-zero value, but carving writes a single one
+        assert_eq![BASE_SIZE * BASE_SIZE, white.carve(&mut grid).len()];
+        assert_eq![Some(&air_of(WHITE_REALITY)), grid.get(150, 50)];
+    }
+
+    #[test]
+    fn carving_is_clipped_to_grid() {
+        let mut grid = Grid::new();
+        grid.resize(40, 40);
+        let base = Base {
+            team: Team::White,
+            position: Vec2::new(5.0, 35.0),
+        };
+        assert_eq![35 * 35, base.carve(&mut grid).len()];
+    }
+
+    #[test]
+    fn restart_keeps_scores() {
+        let mut round = RoundStatus::default();
+        round.base_health[Team::White.index()] = 0;
+        round.end(Team::Black, RoundEnd::BaseDestroyed);
+        assert![!round.is_running()];
+
+        round.restart();
+        assert![round.is_running()];
+        assert_eq![1, round.number];
+        assert_eq![[1, 0], round.scores];
+        assert_eq![[BASE_HEALTH; 2], round.base_health];
+    }
+
+    #[test]
+    fn leader_has_most_rounds() {
+        let mut round = RoundStatus::default();
+        assert_eq![None, round.leader()];
+        round.end(Team::White, RoundEnd::BaseCaptured);
+        assert_eq![Some(Team::White), round.leader()];
+        round.end(Team::Black, RoundEnd::BaseCaptured);
+        round.end(Team::Black, RoundEnd::BaseCaptured);
+        assert_eq![Some(Team::Black), round.leader()];
+    }
+}
@@ -0,0 +1,502 @@
+use crate::game::Reality;
+use failure::{format_err, Error};
+use geometry::grid2d::Grid;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Side length in cells of the square chunks terrain is transferred in
+pub const CHUNK_SIZE: usize = 64;
+/// Shortest run of a single value that `compress` encodes as a run
+const MIN_RUN: usize = 3;
+/// Longest run a single header byte can describe
+const MAX_RUN: usize = 128 + MIN_RUN - 1;
+/// Most bytes a single header byte can copy verbatim
+const MAX_LITERALS: usize = 128;
+
+/// Rectangle of grid cells that all changed to `value`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct GridRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub value: Reality,
+}
+
+/// Encodes changed cells as rectangles of cells with the same value. When a cell changed
+/// several times only its last value is kept.
+///
+/// Cells are first joined into horizontal runs, then runs covering the same columns with the
+/// same value in consecutive rows are joined into rectangles.
+pub fn encode_grid_changes(changes: &[(u32, u32, Reality)]) -> Vec<GridRect> {
+    // Keyed by row first, so cells come out in rows from left to right
+    let mut cells = BTreeMap::new();
+    for (x, y, value) in changes {
+        cells.insert((*y, *x), *value);
+    }
+
+    let mut runs: Vec<GridRect> = Vec::new();
+    for ((y, x), value) in cells {
+        match runs.last_mut() {
+            Some(run) if run.y == y && run.x + run.width == x && run.value == value => {
+                run.width += 1;
+            }
+            _ => runs.push(GridRect {
+                x,
+                y,
+                width: 1,
+                height: 1,
+                value,
+            }),
+        }
+    }
+
+    let mut rects: Vec<GridRect> = Vec::new();
+    // Rectangles ending in the row above and in the current row, by columns and value
+    let mut above = HashMap::new();
+    let mut current = HashMap::new();
+    let mut current_row = None;
+    for run in runs {
+        if current_row != Some(run.y) {
+            above = if current_row.map_or(false, |row| row + 1 == run.y) {
+                std::mem::replace(&mut current, HashMap::new())
+            } else {
+                current.clear();
+                HashMap::new()
+            };
+            current_row = Some(run.y);
+        }
+        let key = (run.x, run.width, run.value);
+        let index = match above.get(&key) {
+            Some(&index) => {
+                let rect: &mut GridRect = &mut rects[index];
+                rect.height += 1;
+                index
+            }
+            None => {
+                rects.push(run);
+                rects.len() - 1
+            }
+        };
+        current.insert(key, index);
+    }
+    rects
+}
+
+/// The cells covered by `rects` with their new values, leaving out cells outside a grid of the
+/// given size
+pub fn decode_grid_changes<'a>(
+    rects: &'a [GridRect],
+    width: usize,
+    height: usize,
+) -> impl Iterator<Item = (u32, u32, Reality)> + 'a {
+    rects.iter().flat_map(move |rect| {
+        let end_x = (u64::from(rect.x) + u64::from(rect.width)).min(width as u64) as u32;
+        let end_y = (u64::from(rect.y) + u64::from(rect.height)).min(height as u64) as u32;
+        (rect.y..end_y).flat_map(move |y| (rect.x..end_x).map(move |x| (x, y, rect.value)))
+    })
+}
+
+/// Compresses bytes as runs of a single value and stretches of verbatim bytes.
+///
+/// Every stretch starts with a header byte. A header below 128 is followed by header + 1
+/// verbatim bytes, any other header is followed by a single byte that is repeated
+/// header - 125 times.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut literals_start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|value| **value == data[i])
+            .count();
+        if run >= MIN_RUN {
+            push_literals(&mut compressed, &data[literals_start..i]);
+            compressed.push((128 + run - MIN_RUN) as u8);
+            compressed.push(data[i]);
+            literals_start = i + run;
+        }
+        i += run;
+    }
+    push_literals(&mut compressed, &data[literals_start..]);
+    compressed
+}
+
+fn push_literals(compressed: &mut Vec<u8>, literals: &[u8]) {
+    for part in literals.chunks(MAX_LITERALS) {
+        compressed.push((part.len() - 1) as u8);
+        compressed.extend_from_slice(part);
+    }
+}
+
+/// Reverses `compress`. Fails unless the data decompresses to exactly `length` bytes.
+pub fn decompress(compressed: &[u8], length: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(length);
+    let mut i = 0;
+    while i < compressed.len() {
+        let header = compressed[i] as usize;
+        i += 1;
+        if header < 128 {
+            let end = i + header + 1;
+            let literals = compressed
+                .get(i..end)
+                .ok_or_else(|| format_err!["Compressed data ends within verbatim bytes"])?;
+            data.extend_from_slice(literals);
+            i = end;
+        } else {
+            let value = compressed
+                .get(i)
+                .ok_or_else(|| format_err!["Compressed data ends before a repeated byte"])?;
+            data.extend(std::iter::repeat(*value).take(header - 128 + MIN_RUN));
+            i += 1;
+        }
+        if data.len() > length {
+            return Err(format_err![
+                "Compressed data is longer than {} bytes",
+                length
+            ]);
+        }
+    }
+    if data.len() != length {
+        return Err(format_err![
+            "Compressed data is {} bytes instead of {}",
+            data.len(),
+            length
+        ]);
+    }
+    Ok(data)
+}
+
+fn chunks_across(size: usize) -> usize {
+    (size + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+/// Number of chunks covering a grid of the given size. Chunks are numbered row by row, the
+/// chunks along the right and bottom edges may be smaller than `CHUNK_SIZE`.
+pub fn chunk_count(width: usize, height: usize) -> usize {
+    chunks_across(width) * chunks_across(height)
+}
+
+/// Cells of chunk `index` row by row, `None` if a grid of this size has no such chunk
+pub fn chunk_cells(
+    index: usize,
+    width: usize,
+    height: usize,
+) -> Option<impl Iterator<Item = (usize, usize)>> {
+    if index >= chunk_count(width, height) {
+        return None;
+    }
+    let across = chunks_across(width);
+    let (x, y) = (index % across * CHUNK_SIZE, index / across * CHUNK_SIZE);
+    let (end_x, end_y) = ((x + CHUNK_SIZE).min(width), (y + CHUNK_SIZE).min(height));
+    Some((y..end_y).flat_map(move |j| (x..end_x).map(move |i| (i, j))))
+}
+
+/// Index of the chunk containing the cell at `x`, `y` in a grid `width` cells wide
+pub fn chunk_index(x: usize, y: usize, width: usize) -> usize {
+    y / CHUNK_SIZE * chunks_across(width) + x / CHUNK_SIZE
+}
+
+/// Hash of the cells of chunk `index` of `grid`, for comparing terrain without sending it.
+/// Uses 64 bit FNV-1a, which gives the same result on every platform.
+pub fn chunk_hash(grid: &Grid<Reality>, index: usize) -> u64 {
+    let (width, height) = grid.get_size();
+    chunk_cells(index, width, height)
+        .into_iter()
+        .flatten()
+        .filter_map(|(x, y)| grid.get(x, y))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, cell| {
+            (hash ^ u64::from(*cell)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Chunks of `grid` whose hash differs from the one at the same index of `hashes`. Chunks
+/// without a hash are not compared.
+pub fn mismatching_chunks(grid: &Grid<Reality>, hashes: &[Option<u64>]) -> Vec<usize> {
+    let (width, height) = grid.get_size();
+    hashes
+        .iter()
+        .take(chunk_count(width, height))
+        .enumerate()
+        .filter_map(|(index, hash)| match hash {
+            Some(hash) if *hash != chunk_hash(grid, index) => Some(index),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The compressed cells of chunk `index` of `grid`, empty if there is no such chunk
+pub fn encode_chunk(grid: &Grid<Reality>, index: usize) -> Vec<u8> {
+    let (width, height) = grid.get_size();
+    let cells: Vec<Reality> = chunk_cells(index, width, height)
+        .into_iter()
+        .flatten()
+        .filter_map(|(x, y)| grid.get(x, y).cloned())
+        .collect();
+    compress(&cells)
+}
+
+/// Writes chunk `index`, as encoded by `encode_chunk`, into `grid`. The grid is left alone if
+/// the chunk doesn't fit it.
+pub fn decode_chunk(grid: &mut Grid<Reality>, index: usize, data: &[u8]) -> Result<(), Error> {
+    let (width, height) = grid.get_size();
+    let cells: Vec<(usize, usize)> = chunk_cells(index, width, height)
+        .ok_or_else(|| format_err!["No chunk {} in a {}x{} grid", index, width, height])?
+        .collect();
+    let values = decompress(data, cells.len())?;
+    for ((x, y), value) in cells.into_iter().zip(values) {
+        grid.set(x, y, value);
+    }
+    Ok(())
+}
+
+/// Terrain that is being received from the server chunk by chunk
+pub struct TerrainDownload {
+    pub grid: Grid<Reality>,
+    received: Vec<bool>,
+    remaining: usize,
+    /// Grid changes that arrived during the download, to be applied in order once it is
+    /// complete
+    pub grid_changes: Vec<GridRect>,
+}
+
+impl TerrainDownload {
+    pub fn new(width: usize, height: usize) -> TerrainDownload {
+        let mut grid = Grid::new();
+        grid.resize(width, height);
+        let count = chunk_count(width, height);
+        TerrainDownload {
+            grid,
+            received: vec![false; count],
+            remaining: count,
+            grid_changes: Vec::new(),
+        }
+    }
+
+    /// Decodes chunk `index` into the grid. A chunk received twice only counts once.
+    pub fn insert(&mut self, index: usize, data: &[u8]) -> Result<(), Error> {
+        decode_chunk(&mut self.grid, index, data)?;
+        if !self.received[index] {
+            self.received[index] = true;
+            self.remaining -= 1;
+        }
+        Ok(())
+    }
+
+    /// Fraction of the chunks received so far, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.received.is_empty() {
+            1.0
+        } else {
+            1.0 - self.remaining as f32 / self.received.len() as f32
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// Server ticks of the grid changes received so far. They are sent unordered, so later ticks
+/// may arrive before earlier ones.
+#[derive(Debug, Default)]
+pub struct ReceivedTicks {
+    /// Every tick from the first one up to this one, exclusive, has been received
+    next: u64,
+    /// Ticks received before some tick preceding them
+    early: BTreeSet<u64>,
+}
+
+impl ReceivedTicks {
+    /// Starts counting at `first`, the first tick the server sends, forgetting earlier ticks
+    pub fn start(&mut self, first: u64) {
+        self.next = first;
+        self.early = self.early.split_off(&first);
+        self.advance();
+    }
+
+    pub fn insert(&mut self, tick: u64) {
+        if tick >= self.next {
+            self.early.insert(tick);
+            self.advance();
+        }
+    }
+
+    /// Whether every tick up to and including `tick` has been received
+    pub fn has_all(&self, tick: u64) -> bool {
+        tick < self.next
+    }
+
+    fn advance(&mut self) {
+        while self.early.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut cells: Vec<(u32, u32, Reality)>) -> Vec<(u32, u32, Reality)> {
+        cells.sort_by_key(|(x, y, _)| (*y, *x));
+        cells
+    }
+
+    #[test]
+    fn square_becomes_single_rect() {
+        let changes: Vec<_> = (0..7)
+            .flat_map(|i| (0..7).map(move |j| (10 + i, 20 + j, 0)))
+            .collect();
+        let rects = encode_grid_changes(&changes);
+        assert_eq![
+            vec![GridRect {
+                x: 10,
+                y: 20,
+                width: 7,
+                height: 7,
+                value: 0
+            }],
+            rects
+        ];
+        let decoded: Vec<_> = decode_grid_changes(&rects, 100, 100).collect();
+        assert_eq![sorted(changes), decoded];
+    }
+
+    #[test]
+    fn circle_roundtrip() {
+        let changes: Vec<_> = (-3i32..=3)
+            .flat_map(|i| (-3i32..=3).map(move |j| (i, j)))
+            .filter(|(i, j)| i * i + j * j <= 9)
+            .map(|(i, j)| ((5 + i) as u32, (5 + j) as u32, 255))
+            .collect();
+        let rects = encode_grid_changes(&changes);
+        // Rows of widths 1, 5, 5, 7, 5, 5 and 1
+        assert_eq![5, rects.len()];
+        let decoded: Vec<_> = decode_grid_changes(&rects, 100, 100).collect();
+        assert_eq![sorted(changes), sorted(decoded)];
+    }
+
+    #[test]
+    fn last_change_of_a_cell_wins() {
+        let changes = vec![(1, 1, 0), (2, 1, 0), (1, 1, 64), (2, 1, 0)];
+        let decoded: Vec<_> = decode_grid_changes(&encode_grid_changes(&changes), 10, 10).collect();
+        assert_eq![vec![(1, 1, 64), (2, 1, 0)], decoded];
+    }
+
+    #[test]
+    fn separate_rows_are_not_joined() {
+        let changes = vec![(0, 0, 0), (1, 0, 0), (0, 2, 0), (1, 2, 0)];
+        assert_eq![2, encode_grid_changes(&changes).len()];
+    }
+
+    #[test]
+    fn decoding_is_clipped_to_grid() {
+        let rects = [GridRect {
+            x: 8,
+            y: 9,
+            width: u32::max_value(),
+            height: 5,
+            value: 1,
+        }];
+        let decoded: Vec<_> = decode_grid_changes(&rects, 10, 10).collect();
+        assert_eq![vec![(8, 9, 1), (9, 9, 1)], decoded];
+    }
+
+    #[test]
+    fn compression_roundtrip() {
+        let mut data = vec![7; 300];
+        data.extend((0..200).map(|x| x as u8));
+        data.extend_from_slice(&[1, 1, 2, 2, 2, 3]);
+        let compressed = compress(&data);
+        // Runs of 130, 130 and 40 sevens, 0 to 199 and 1 1 verbatim in two stretches, a run of
+        // 2 2 2 and a verbatim 3
+        assert_eq![3 * 2 + 2 + 202 + 2 + 2, compressed.len()];
+        assert_eq![data, decompress(&compressed, data.len()).unwrap()];
+        assert![compress(&[]).is_empty()];
+    }
+
+    #[test]
+    fn decompress_rejects_bad_data() {
+        let compressed = compress(&[5; 10]);
+        assert![decompress(&compressed, 9).is_err()];
+        assert![decompress(&compressed, 11).is_err()];
+        assert![decompress(&compressed[..1], 10).is_err()];
+        assert![decompress(&[3, 1, 2], 4).is_err()];
+    }
+
+    #[test]
+    fn chunks_cover_grid_once() {
+        let (width, height) = (150, 70);
+        assert_eq![6, chunk_count(width, height)];
+        let mut covered = vec![0; width * height];
+        for index in 0..chunk_count(width, height) {
+            for (x, y) in chunk_cells(index, width, height).unwrap() {
+                covered[y * width + x] += 1;
+            }
+        }
+        assert![covered.iter().all(|count| *count == 1)];
+        assert![chunk_cells(6, width, height).is_none()];
+    }
+
+    #[test]
+    fn download_rebuilds_grid() {
+        let mut grid = Grid::new();
+        grid.resize(100, 80);
+        for i in 0..100 {
+            for j in 0..80 {
+                grid.set(i, j, ((i / 3 + j) % 256) as Reality);
+            }
+        }
+        let mut download = TerrainDownload::new(100, 80);
+        assert_eq![0.0, download.progress()];
+        for index in (0..chunk_count(100, 80)).rev() {
+            download.insert(index, &encode_chunk(&grid, index)).unwrap();
+        }
+        assert![download.insert(4, &[]).is_err()];
+        assert![download.is_complete()];
+        for i in 0..100 {
+            for j in 0..80 {
+                assert_eq![grid.get(i, j), download.grid.get(i, j)];
+            }
+        }
+    }
+
+    #[test]
+    fn changed_chunks_mismatch() {
+        let mut grid = Grid::new();
+        grid.resize(150, 70);
+        let mut hashes: Vec<Option<u64>> = (0..chunk_count(150, 70))
+            .map(|index| Some(chunk_hash(&grid, index)))
+            .collect();
+        assert![mismatching_chunks(&grid, &hashes).is_empty()];
+
+        grid.set(130, 65, 1);
+        grid.set(10, 10, 1);
+        assert_eq![5, chunk_index(130, 65, 150)];
+        assert_eq![vec![0, 5], mismatching_chunks(&grid, &hashes)];
+        hashes[0] = None;
+        assert_eq![vec![5], mismatching_chunks(&grid, &hashes)];
+    }
+
+    #[test]
+    fn ticks_are_complete_without_gaps() {
+        let mut ticks = ReceivedTicks::default();
+        ticks.insert(3);
+        ticks.insert(12);
+        ticks.insert(11);
+        ticks.start(10);
+        assert![!ticks.has_all(10)];
+        ticks.insert(10);
+        assert![ticks.has_all(12)];
+        assert![!ticks.has_all(13)];
+        ticks.insert(15);
+        ticks.insert(14);
+        assert![!ticks.has_all(14)];
+        ticks.insert(13);
+        assert![ticks.has_all(15)];
+        ticks.insert(7);
+        assert![!ticks.has_all(16)];
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+/// Duration of a single simulation tick, shared by client and server
+pub const TICK_DURATION: Duration = Duration::from_millis(8);
+/// Upper bound on the ticks run by a single `FixedTimestep::advance`. When the simulation falls
+/// further behind than this, the excess time is dropped instead of trying to catch up.
+const MAX_TICKS_PER_ADVANCE: u32 = 10;
+
+/// Accumulator-based scheduler that turns wall-clock time into a whole number of fixed-length
+/// simulation ticks, independent of how often it is polled.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulator: Duration,
+    previous: Instant,
+}
+
+impl FixedTimestep {
+    pub fn new(tick: Duration, start: Instant) -> FixedTimestep {
+        FixedTimestep {
+            tick,
+            accumulator: Duration::new(0, 0),
+            previous: start,
+        }
+    }
+
+    /// Accumulate the time passed since the previous call and return the number of ticks to run
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        self.accumulator += now.saturating_duration_since(self.previous);
+        self.previous = now.max(self.previous);
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            ticks += 1;
+        }
+        if ticks > MAX_TICKS_PER_ADVANCE {
+            ticks = MAX_TICKS_PER_ADVANCE;
+        }
+        ticks
+    }
+
+    /// How far the accumulated time has progressed towards the next tick, in [0, 1).
+    /// Used to render between the previous and the current tick.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.tick.as_secs_f32()
+    }
+
+    /// Time left until `advance` will return at least one tick
+    pub fn until_next_tick(&self, now: Instant) -> Duration {
+        let elapsed = self.accumulator + now.saturating_duration_since(self.previous);
+        self.tick
+            .checked_sub(elapsed)
+            .unwrap_or_else(|| Duration::new(0, 0))
+    }
+}
+
+/// Time left of a frame that began at `frame_start` when running at most `fps` frames per second.
+/// An `fps` of 0 or less means there is no cap.
+pub fn until_next_frame(frame_start: Instant, now: Instant, fps: f32) -> Duration {
+    if fps <= 0.0 {
+        return Duration::new(0, 0);
+    }
+    Duration::from_secs_f32(1.0 / fps)
+        .checked_sub(now.saturating_duration_since(frame_start))
+        .unwrap_or_else(|| Duration::new(0, 0))
+}
+
+/// Number of simulation ticks in `seconds`, rounded to the nearest tick. Negative durations are
+/// no ticks.
+pub fn seconds_to_ticks(seconds: f32) -> u64 {
+    (seconds.max(0.0) / TICK_DURATION.as_secs_f32()).round() as u64
+}
+
+/// Simulation time after `ticks` ticks
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks * TICK_DURATION.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn ticks_follow_elapsed_time() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(ms(10), start);
+
+        assert_eq![0, timestep.advance(start + ms(5))];
+        assert_eq![1, timestep.advance(start + ms(12))];
+        assert_eq![2, timestep.advance(start + ms(35))];
+        assert_eq![0, timestep.advance(start + ms(39))];
+    }
+
+    #[test]
+    fn alpha_is_fraction_of_next_tick() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(ms(10), start);
+
+        timestep.advance(start + ms(25));
+
+        assert![(timestep.alpha() - 0.5).abs() < 1e-4];
+        assert_eq![ms(5), timestep.until_next_tick(start + ms(25))];
+        assert_eq![ms(0), timestep.until_next_tick(start + ms(40))];
+    }
+
+    #[test]
+    fn catching_up_is_limited() {
+        let start = Instant::now();
+        let mut timestep = FixedTimestep::new(ms(10), start);
+
+        assert_eq![MAX_TICKS_PER_ADVANCE, timestep.advance(start + ms(10_000))];
+        assert_eq![0, timestep.advance(start + ms(10_005))];
+    }
+
+    #[test]
+    fn going_back_in_time_runs_no_ticks() {
+        let start = Instant::now() + ms(100);
+        let mut timestep = FixedTimestep::new(ms(10), start);
+
+        assert_eq![0, timestep.advance(start - ms(50))];
+        assert_eq![1, timestep.advance(start + ms(10))];
+    }
+
+    #[test]
+    fn frame_cap() {
+        let start = Instant::now();
+        assert_eq![ms(0), until_next_frame(start, start + ms(50), 0.0)];
+        assert_eq![ms(15), until_next_frame(start, start + ms(5), 50.0)];
+        assert_eq![ms(0), until_next_frame(start, start + ms(30), 50.0)];
+    }
+
+    #[test]
+    fn seconds_in_ticks() {
+        assert_eq![125, seconds_to_ticks(1.0)];
+        assert_eq![0, seconds_to_ticks(0.0)];
+        assert_eq![0, seconds_to_ticks(-3.0)];
+        assert_eq![Duration::from_secs(2), ticks_to_duration(250)];
+    }
+}
@@ -0,0 +1,196 @@
+use crate::game::{Crater, CraterShape};
+use failure::{format_err, Error};
+
+/// Weapon definitions compiled into the game, used until a server sends its own
+static DEFAULT_WEAPONS: &str = include_str!["../../weapons.toml"];
+
+/// Index of a weapon in the `WeaponRegistry`
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct Weapon(pub u8);
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct WeaponStats {
+    pub name: String,
+    /// Number of bullet animation frames horizontally in the animation block
+    pub width: usize,
+    /// Number of bullet animation frames vertically in the animation block
+    pub height: usize,
+    /// Region of the bullets texture holding the animation frames
+    pub animation_block_begin: (f32, f32),
+    pub animation_block_end: (f32, f32),
+    pub sprite_width: f32,
+    pub sprite_height: f32,
+    /// Radius of the crater blown into the terrain where a bullet hits
+    pub destruction: i32,
+    #[serde(default)]
+    pub crater: CraterShape,
+    /// See `Crater::falloff`
+    #[serde(default)]
+    pub falloff: f32,
+    /// Health taken from a player hit by a bullet
+    pub damage: u32,
+    pub bullet_count: u8,
+    pub spread: f32,
+    pub speed: f32,
+    /// Region of the weapons texture that shows the weapon itself
+    pub weapon_uv_begin: (f32, f32),
+    pub weapon_uv_end: (f32, f32),
+    /// Ticks between two shots
+    pub fire_interval: usize,
+    /// Shots before the weapon has to be reloaded
+    pub magazine_size: u32,
+    /// Ticks it takes to reload an empty magazine
+    pub reload_time: usize,
+    /// Ticks a bullet flies before it disappears, its range is `speed * lifetime`
+    pub lifetime: usize,
+}
+
+/// All weapons of a game. The server sends its registry to clients in `Welcome`, so both sides
+/// use the same stats.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct WeaponRegistry {
+    #[serde(rename = "weapon")]
+    weapons: Vec<WeaponStats>,
+}
+
+impl Default for WeaponRegistry {
+    fn default() -> Self {
+        parse_weapons(DEFAULT_WEAPONS).expect("Built-in weapons.toml is invalid")
+    }
+}
+
+impl WeaponStats {
+    pub fn crater(&self) -> Crater {
+        Crater {
+            shape: self.crater.clone(),
+            radius: self.destruction,
+            falloff: self.falloff,
+        }
+    }
+}
+
+impl WeaponRegistry {
+    pub fn len(&self) -> usize {
+        self.weapons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weapons.is_empty()
+    }
+
+    pub fn contains(&self, weapon: Weapon) -> bool {
+        (weapon.0 as usize) < self.weapons.len()
+    }
+
+    /// Stats of `weapon`, or of the first weapon if there is no such weapon
+    pub fn get(&self, weapon: Weapon) -> &WeaponStats {
+        self.weapons
+            .get(weapon.0 as usize)
+            .unwrap_or(&self.weapons[0])
+    }
+
+    /// The weapon with the given name
+    pub fn find(&self, name: &str) -> Option<Weapon> {
+        self.weapons
+            .iter()
+            .position(|stats| stats.name == name)
+            .map(|index| Weapon(index as u8))
+    }
+
+    /// The weapon after `weapon`, wrapping around to the first
+    pub fn next(&self, weapon: Weapon) -> Weapon {
+        Weapon(((weapon.0 as usize + 1) % self.weapons.len()) as u8)
+    }
+
+    /// The weapon before `weapon`, wrapping around to the last
+    pub fn previous(&self, weapon: Weapon) -> Weapon {
+        let count = self.weapons.len();
+        Weapon(((weapon.0 as usize % count + count - 1) % count) as u8)
+    }
+
+    /// Checks the invariants the game relies on: at least one and at most 256 weapons, and
+    /// every weapon has at least one animation frame, bullets in its magazine and bullets that
+    /// live for at least a tick
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.weapons.is_empty() {
+            return Err(format_err!["No weapons defined"]);
+        }
+        if self.weapons.len() > 256 {
+            return Err(format_err![
+                "At most 256 weapons are supported, got {}",
+                self.weapons.len()
+            ]);
+        }
+        for stats in &self.weapons {
+            if stats.width == 0 || stats.height == 0 {
+                return Err(format_err![
+                    "Weapon {:?} has no animation frames",
+                    stats.name
+                ]);
+            }
+            if stats.magazine_size == 0 {
+                return Err(format_err!["Weapon {:?} has an empty magazine", stats.name]);
+            }
+            if stats.lifetime == 0 {
+                return Err(format_err![
+                    "Weapon {:?} has no bullet lifetime",
+                    stats.name
+                ]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses and validates weapon definitions in the format of `weapons.toml`
+pub fn parse_weapons(contents: &str) -> Result<WeaponRegistry, Error> {
+    let registry: WeaponRegistry = toml::from_str(contents)?;
+    registry.validate()?;
+    Ok(registry)
+}
+
+/// Reads weapon definitions from a TOML file
+pub fn read_weapons(path: &str) -> Result<WeaponRegistry, Error> {
+    parse_weapons(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_weapons_are_valid() {
+        let weapons = WeaponRegistry::default();
+        assert_eq![2, weapons.len()];
+        assert_eq![Some(Weapon(1)), weapons.find("Ak47")];
+        assert_eq![30, weapons.get(Weapon(1)).magazine_size];
+    }
+
+    #[test]
+    fn weapons_cycle_in_both_directions() {
+        let weapons = WeaponRegistry::default();
+        for index in 0..weapons.len() {
+            let weapon = Weapon(index as u8);
+            assert_eq![weapon, weapons.previous(weapons.next(weapon))];
+        }
+        assert_eq![Weapon(1), weapons.next(Weapon(0))];
+        assert_eq![Weapon(0), weapons.next(Weapon(1))];
+        assert_eq![Weapon(1), weapons.previous(Weapon(0))];
+    }
+
+    #[test]
+    fn unknown_weapon_falls_back_to_first() {
+        let weapons = WeaponRegistry::default();
+        assert![!weapons.contains(Weapon(200))];
+        assert_eq!["Hellfire", weapons.get(Weapon(200)).name];
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        assert![parse_weapons("").is_err()];
+        let no_frames = DEFAULT_WEAPONS.replacen("width = 10", "width = 0", 1);
+        assert![parse_weapons(&no_frames).is_err()];
+        let missing_field = DEFAULT_WEAPONS.replacen("spread = 0.3", "", 1);
+        assert![parse_weapons(&missing_field).is_err()];
+    }
+}
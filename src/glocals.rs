@@ -6,10 +6,12 @@ use std::{
 };
 
 pub mod config;
+pub mod controls;
 pub mod log;
 pub mod msg;
 
 pub use self::config::*;
+pub use controls::*;
 pub use log::Log;
 pub use msg::*;
 
@@ -1,4 +1,5 @@
 use config::config;
+use failure::Error;
 
 // enum Key { A, B, C};
 // impl ConfigValue for Key {
@@ -30,21 +31,55 @@ struct Config {
         }
     }
     controls: ControlsConfig {
-        down: String,
-        // up: String,
-        // left: String,
-        // right: String,
+        // Names of winit keys like "Left" or "LShift", or "MouseLeft", "MouseRight" and
+        // "MouseMiddle". Empty entries keep the default binding
+        move_left: String,
+        move_right: String,
+        move_up: String,
+        move_down: String,
+        jump: String,
+        walk: String,
+        fire: String,
+        switch_weapon: String,
+        switch_reality: String,
     }
     server: ServerConfig {
-        // Ticks between sending full state
+        // Ticks between sending full state instead of a delta, 0 only sends full state when
+        // the client has no usable baseline
         ticks_per_full_state: u32,
+        // Distance around a client's view within which entities and grid changes are sent
+        interest_margin: f32,
+        // Seconds of warmup before a match starts, counted once both teams have players
+        warmup_time: f32,
+        // Seconds a match lasts, 0 for no time limit
+        time_limit: f32,
+        // Rounds a team must win to win the match, 0 for no score limit
+        score_limit: u32,
+        // Seconds between the end of a match and the start of the next one in a new world
+        restart_delay: f32,
+        // Terrain chunks sent to a joining client per tick, at least one is always sent
+        terrain_chunks_per_tick: u32,
+        // Seconds between sending terrain hashes that clients check their terrain against, 0
+        // never sends them
+        terrain_hash_interval: f32,
         // TODO: max bandwidth perhaps. If limit is reached, ticks per send will just have to
         // increase.
     }
     client: ClientConfig {
         snapshot_rate: f32,
+        // Frames per second cap, 0 means uncapped
         fps: f32,
+        // Seconds that remote players and bullets are rendered in the past
+        interpolation_delay: f32,
+        // Maximum seconds to extrapolate remote entities when snapshots are missing
+        max_extrapolation: f32,
     }
 }
 
 }
+
+/// Reads a `Config` from a TOML file
+pub fn read_config(path: &str) -> Result<Config, Error> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&contents)?)
+}
@@ -0,0 +1,259 @@
+use crate::glocals::{ControlsConfig, InputCommand};
+use failure::{format_err, Error};
+use std::collections::HashMap;
+use winit::{MouseButton, VirtualKeyCode};
+
+/// Something a player can do, independent of the key or button that triggers it
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    /// Fly upwards, only has an effect without gravity
+    MoveUp,
+    MoveDown,
+    /// Jump off the ground, only has an effect with gravity
+    Jump,
+    /// Move slower, for precise positioning
+    Walk,
+    Fire,
+    SwitchWeapon,
+    /// Move to the other reality, turning the terrain around the player into its air
+    SwitchReality,
+}
+
+impl Action {
+    pub const ALL: [Action; 9] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::Jump,
+        Action::Walk,
+        Action::Fire,
+        Action::SwitchWeapon,
+        Action::SwitchReality,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// The set of actions that are currently held down
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ActionState {
+    held: u32,
+}
+
+impl ActionState {
+    pub fn is_down(&self, action: Action) -> bool {
+        self.held & action.bit() != 0
+    }
+
+    pub fn set(&mut self, action: Action, down: bool) {
+        if down {
+            self.held |= action.bit();
+        } else {
+            self.held &= !action.bit();
+        }
+    }
+
+    pub fn apply(&mut self, command: InputCommand) {
+        match command {
+            InputCommand::Pressed(action) => self.set(action, true),
+            InputCommand::Released(action) => self.set(action, false),
+        }
+    }
+
+    /// Commands that turn `self` into `next`
+    pub fn changes_to(&self, next: &ActionState) -> Vec<InputCommand> {
+        Action::ALL
+            .iter()
+            .filter(|action| self.is_down(**action) != next.is_down(**action))
+            .map(|action| {
+                if next.is_down(*action) {
+                    InputCommand::Pressed(*action)
+                } else {
+                    InputCommand::Released(*action)
+                }
+            })
+            .collect()
+    }
+}
+
+/// A physical key or mouse button
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Parses a winit key name like `Left`, `LShift` or `Key1`, or one of `MouseLeft`,
+    /// `MouseRight` and `MouseMiddle`
+    pub fn parse(name: &str) -> Result<Binding, Error> {
+        match name {
+            "MouseLeft" => Ok(Binding::Mouse(MouseButton::Left)),
+            "MouseRight" => Ok(Binding::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Ok(Binding::Mouse(MouseButton::Middle)),
+            _ => toml::Value::String(name.into())
+                .try_into()
+                .map(Binding::Key)
+                .map_err(|_| format_err!["Unknown key or mouse button: {:?}", name]),
+        }
+    }
+
+    fn is_down(self, input: &winput::Input) -> bool {
+        match self {
+            Binding::Key(key) => input.is_key_down(key),
+            Binding::Mouse(button) => input.is_mouse_button_down(button),
+        }
+    }
+}
+
+/// Maps keys and mouse buttons to actions
+#[derive(Clone, Debug)]
+pub struct Controls {
+    bindings: HashMap<Action, Binding>,
+}
+
+impl Default for Controls {
+    fn default() -> Controls {
+        let bindings = [
+            (Action::MoveLeft, Binding::Key(VirtualKeyCode::Left)),
+            (Action::MoveRight, Binding::Key(VirtualKeyCode::Right)),
+            (Action::MoveUp, Binding::Key(VirtualKeyCode::Up)),
+            (Action::MoveDown, Binding::Key(VirtualKeyCode::Down)),
+            (Action::Jump, Binding::Key(VirtualKeyCode::Up)),
+            (Action::Walk, Binding::Key(VirtualKeyCode::LShift)),
+            (Action::Fire, Binding::Mouse(MouseButton::Left)),
+            (Action::SwitchWeapon, Binding::Key(VirtualKeyCode::Q)),
+            (Action::SwitchReality, Binding::Key(VirtualKeyCode::R)),
+        ];
+        Controls {
+            bindings: bindings.iter().cloned().collect(),
+        }
+    }
+}
+
+impl Controls {
+    /// Default controls with the bindings in `config` applied on top. Empty entries keep the
+    /// default binding.
+    pub fn from_config(config: &ControlsConfig) -> Result<Controls, Error> {
+        let mut controls = Controls::default();
+        let entries = [
+            (Action::MoveLeft, &config.move_left),
+            (Action::MoveRight, &config.move_right),
+            (Action::MoveUp, &config.move_up),
+            (Action::MoveDown, &config.move_down),
+            (Action::Jump, &config.jump),
+            (Action::Walk, &config.walk),
+            (Action::Fire, &config.fire),
+            (Action::SwitchWeapon, &config.switch_weapon),
+            (Action::SwitchReality, &config.switch_reality),
+        ];
+        for (action, name) in entries.iter() {
+            if !name.is_empty() {
+                controls.bind(*action, Binding::parse(name)?);
+            }
+        }
+        Ok(controls)
+    }
+
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.bindings.insert(action, binding);
+    }
+
+    pub fn binding(&self, action: Action) -> Option<Binding> {
+        self.bindings.get(&action).cloned()
+    }
+
+    /// The actions whose keys or buttons are held down in `input`
+    pub fn actions(&self, input: &winput::Input) -> ActionState {
+        let mut state = ActionState::default();
+        for (action, binding) in &self.bindings {
+            if binding.is_down(input) {
+                state.set(*action, true);
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::{ElementState, KeyboardInput, ModifiersState};
+
+    fn press(input: &mut winput::Input, key: VirtualKeyCode) {
+        input.register_key(&KeyboardInput {
+            scancode: 0,
+            state: ElementState::Pressed,
+            virtual_keycode: Some(key),
+            modifiers: ModifiersState::default(),
+        });
+    }
+
+    #[test]
+    fn parse_bindings() {
+        assert_eq![
+            Binding::Key(VirtualKeyCode::LShift),
+            Binding::parse("LShift").unwrap()
+        ];
+        assert_eq![
+            Binding::Mouse(MouseButton::Right),
+            Binding::parse("MouseRight").unwrap()
+        ];
+        assert![Binding::parse("NotAKey").is_err()];
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let config = ControlsConfig {
+            move_left: "A".into(),
+            ..ControlsConfig::default()
+        };
+        let controls = Controls::from_config(&config).unwrap();
+        assert_eq![
+            Some(Binding::Key(VirtualKeyCode::A)),
+            controls.binding(Action::MoveLeft)
+        ];
+        assert_eq![
+            Some(Binding::Key(VirtualKeyCode::Right)),
+            controls.binding(Action::MoveRight)
+        ];
+    }
+
+    #[test]
+    fn one_key_can_trigger_several_actions() {
+        let mut input = winput::Input::default();
+        press(&mut input, VirtualKeyCode::Up);
+        let actions = Controls::default().actions(&input);
+        assert![actions.is_down(Action::MoveUp)];
+        assert![actions.is_down(Action::Jump)];
+        assert![!actions.is_down(Action::MoveDown)];
+    }
+
+    #[test]
+    fn changes_replay_to_same_state() {
+        let mut before = ActionState::default();
+        before.set(Action::Fire, true);
+        before.set(Action::MoveLeft, true);
+        let mut after = ActionState::default();
+        after.set(Action::MoveLeft, true);
+        after.set(Action::Walk, true);
+
+        let changes = before.changes_to(&after);
+        assert_eq![
+            vec![
+                InputCommand::Pressed(Action::Walk),
+                InputCommand::Released(Action::Fire)
+            ],
+            changes
+        ];
+        for change in changes {
+            before.apply(change);
+        }
+        assert_eq![after, before];
+    }
+}
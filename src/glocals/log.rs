@@ -12,6 +12,8 @@ pub enum Log {
     Static(&'static str),
     StaticDynamic(&'static str, &'static str, String),
     StaticDynamics(&'static str, Vec<(&'static str, String)>),
+    /// Chunks of our terrain that differ from the server's
+    TerrainMismatch(Vec<usize>),
     U64(&'static str, &'static str, u64),
     U8(&'static str, &'static str, u8),
     Usize(&'static str, &'static str, usize),
@@ -56,6 +58,9 @@ impl std::fmt::Display for Log {
                 }
                 Ok(())
             }
+            Log::TerrainMismatch(chunks) => {
+                write![f, "Terrain differs from the server, chunks={:?}", chunks]
+            }
             Log::U8(msg, key, value) => write![f, "{}, {}={}", msg, key, value],
             Log::U64(msg, key, value) => write![f, "{}, {}={}", msg, key, value],
             Log::Usize(msg, key, value) => write![f, "{}, {}={}", msg, key, value],
@@ -1,13 +1,40 @@
-use crate::game::{Bullet, Id, PlayerData};
+use crate::game::{GridRect, Id, RoundStatus, Scoreboard, Weapon, WeaponRegistry};
+use crate::glocals::Action;
 use bincode;
 use failure::Error;
-use std::convert::TryFrom;
+use geometry::{cam::Camera, vec::Vec2};
 
 /// Message sent between from client to server
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ClientMessage {
     Join,
-    Input(Vec<InputCommand>),
+    /// Input commands for a single client tick. `sequence` increases by one every tick, so the
+    /// server can tell the client which inputs it has processed.
+    /// `aim` is the world-space angle from the player to the cursor, `None` when the client has
+    /// no cursor to aim with.
+    Input {
+        sequence: u32,
+        commands: Vec<InputCommand>,
+        aim: Option<f32>,
+    },
+    /// Acknowledges that the snapshot with this number was received and decoded, so the server
+    /// can use it as the baseline for delta encoding.
+    AckSnapshot(u32),
+    /// The part of the world the client is looking at. The server only sends entities and grid
+    /// changes near this view. Clients that never send it receive everything.
+    View(Camera),
+    /// Requests our player to hold `Weapon` from now on. Sent on the same ordered stream as
+    /// `Input`, the server queues it behind the inputs sent before it and switches once they
+    /// have been applied, so their shots are fired with the previous weapon.
+    SwitchWeapon(Weapon),
+    /// Asks the server for a `ServerMessage::Scoreboard` right away
+    RequestScoreboard,
+    /// Asks the server to send these terrain chunks again, because they differ from the hashes
+    /// in `ServerMessage::TerrainHashes`
+    RequestTerrainChunks(Vec<u32>),
+    /// Sent when the client disconnects, so the server can remove the player right away instead
+    /// of waiting for the connection to time out.
+    Leave,
 }
 impl ClientMessage {
     pub fn serialize(&self) -> Vec<u8> {
@@ -21,24 +48,71 @@ impl ClientMessage {
 /// Message sent between from server to client
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerMessage {
+    /// Answer to `Join`. The terrain follows in `TerrainChunk` messages, the client holds off
+    /// simulation until it has all of them.
     Welcome {
         your_id: Id,
         world_width: usize,
         world_height: usize,
-        world_seed: [f32; 3],
+        /// Weapons `Weapon` ids refer to
+        weapons: WeaponRegistry,
+        /// Generation of the current world, see `DeltaState::world`
+        world: u32,
+        /// Tick of the first `DeltaState` sent to the receiver
+        tick: u64,
     },
-    /// Full state, sent unreliably.
-    /// Could potentially also be used for a part of the state. In any case, the client
-    /// is not supposed to e.g. delete a player or bullet that is not present in such state.
-    /// Deletion of entities happens via `ServerMessage::DeltaState`
+    /// Chunk `index` of the current terrain, encoded with `game::encode_chunk`. Sent reliably
+    /// and in order, a few per tick, after `Welcome` and on `ClientMessage::RequestTerrainChunks`.
+    TerrainChunk { index: u32, data: Vec<u8> },
+    /// Hashes of every terrain chunk as computed by `game::chunk_hash`, sent periodically and in
+    /// order. They cover the grid changes of every `DeltaState` up to and including the one of
+    /// `tick`, chunks with grid changes that haven't been sent yet have no hash.
+    TerrainHashes { tick: u64, hashes: Vec<Option<u64>> },
+    /// Snapshot of players and bullets, sent unreliably.
+    /// `data` is encoded with `game::encode_snapshot` against the snapshot numbered `baseline`,
+    /// which the client has acknowledged, or in full if `baseline` is `None`.
+    /// The client is not supposed to e.g. delete a player or bullet that is missing from a
+    /// snapshot, deletion of entities happens via `ServerMessage::DeltaState`
     State {
-        players: Vec<PlayerData>,
-        bullets: Vec<Bullet>,
+        snapshot: u32,
+        /// Server tick the snapshot was taken at, clients interpolate between snapshots by it
+        tick: u64,
+        baseline: Option<u32>,
+        /// Sequence number of the last `ClientMessage::Input` processed for the receiver
+        last_input_sequence: u32,
+        data: Vec<u8>,
     },
     /// Part of state update that is represented by a _change_, and thus sent _reliably_.
+    /// `removed` also lists entities that left the receiver's area of interest, they are sent
+    /// again in `State` when they come back into view.
+    /// `grid_changes` is encoded with `game::encode_grid_changes`, every cell appears at most
+    /// once.
     DeltaState {
         removed: Vec<(Id, EntityType)>,
-        grid_changes: Vec<(u32, u32, u8)>,
+        grid_changes: Vec<GridRect>,
+        /// Generation of the world `grid_changes` belong to. This message is unordered, so it
+        /// can arrive after the `NewWorld` of the next world, whose grid it must not touch.
+        world: u32,
+        /// Server tick this was sent at, one `DeltaState` is sent every tick. Once it removed
+        /// entities, `State` messages of earlier ticks are ignored, they would bring them back.
+        tick: u64,
+    },
+    /// Events of a single tick, sent reliably and in order to every client regardless of what
+    /// it is looking at
+    Events(Vec<GameEvent>),
+    /// Scores, base health and state of the current round. Sent reliably and in order when a
+    /// client joins and whenever it changes.
+    Round(RoundStatus),
+    /// Kills, deaths and state of the match. Sent when it changes and on request.
+    Scoreboard(Scoreboard),
+    /// The server replaced the world between matches. Sent reliably and in order, before the
+    /// grid changes of the new world.
+    NewWorld {
+        world_width: usize,
+        world_height: usize,
+        world_seed: [f32; 3],
+        /// Generation of the new world, increased by one with every new world
+        world: u32,
     },
 }
 impl ServerMessage {
@@ -49,7 +123,21 @@ impl ServerMessage {
         Ok(bincode::deserialize(bytes)?)
     }
 }
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+/// Something that happened in the game that clients may want to show
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// A player ran out of health, `killer` fired the last bullet that hit it
+    Died {
+        id: Id,
+        killer: Option<Id>,
+    },
+    Respawned {
+        id: Id,
+        position: Vec2,
+    },
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum EntityType {
     Player,
     Bullet,
@@ -57,37 +145,9 @@ pub enum EntityType {
 
 // ---
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// Change in the actions held by a player, see `ActionState::changes_to`
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
 pub enum InputCommand {
-    Keyboard {
-        state: winit::ElementState,
-        virtual_keycode: winit::VirtualKeyCode,
-        modifiers: winit::ModifiersState,
-    },
-    Mouse {
-        position: (f32, f32),
-        state: winit::ElementState,
-        button: winit::MouseButton,
-        modifiers: winit::ModifiersState,
-    },
-}
-
-impl TryFrom<winit::KeyboardInput> for InputCommand {
-    type Error = ();
-
-    fn try_from(key: winit::KeyboardInput) -> Result<Self, Self::Error> {
-        match key {
-            winit::KeyboardInput {
-                state,
-                virtual_keycode: Some(virtual_keycode),
-                modifiers,
-                ..
-            } => Ok(Self::Keyboard {
-                state,
-                virtual_keycode: virtual_keycode,
-                modifiers,
-            }),
-            _ => Err(()),
-        }
-    }
+    Pressed(Action),
+    Released(Action),
 }
@@ -1,3 +1,4 @@
+#![feature(checked_duration_since)]
 #![feature(map_get_key_value)]
 #![feature(test)]
 extern crate test; // Required for testing, even though extern crate is no longer needed in the 2018 version, this is a special case
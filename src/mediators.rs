@@ -1,3 +1,4 @@
 pub mod collision;
 pub mod game_shell;
 pub mod testtools;
+pub mod worldgen;
@@ -0,0 +1,169 @@
+use crate::game::Reality;
+use geometry::grid2d::Grid;
+use rand::seq::SliceRandom;
+use rand_pcg::Pcg64Mcg;
+
+/// Distance between samples in noise space per grid cell
+const SCALE: f32 = 0.01;
+const OCTAVES: usize = 4;
+const PERSISTENCE: f32 = 0.5;
+/// Noise values at or below this threshold become air
+const AIR_THRESHOLD: f32 = 0.0;
+
+/// Seedable 2D gradient noise (improved Perlin noise) computed entirely on the CPU.
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u128) -> Perlin {
+        let mut random = Pcg64Mcg::new(seed);
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut random);
+        let mut permutation = [0; 512];
+        for (idx, value) in permutation.iter_mut().enumerate() {
+            *value = table[idx % 256];
+        }
+        Perlin { permutation }
+    }
+
+    /// Noise value at a point, roughly in the range [-1, 1]
+    pub fn noise(&self, x: f32, y: f32) -> f32 {
+        let (xf, yf) = (x.floor(), y.floor());
+        let xi = (xf as i32 & 255) as usize;
+        let yi = (yf as i32 & 255) as usize;
+        let (x, y) = (x - xf, y - yf);
+        let (u, v) = (fade(x), fade(y));
+
+        let p = &self.permutation;
+        let aa = p[p[xi] as usize + yi];
+        let ab = p[p[xi] as usize + yi + 1];
+        let ba = p[p[xi + 1] as usize + yi];
+        let bb = p[p[xi + 1] as usize + yi + 1];
+
+        lerp(
+            v,
+            lerp(u, gradient(aa, x, y), gradient(ba, x - 1.0, y)),
+            lerp(u, gradient(ab, x, y - 1.0), gradient(bb, x - 1.0, y - 1.0)),
+        )
+    }
+
+    /// Sum of `octaves` layers of noise, each with double the frequency and `persistence` times
+    /// the amplitude of the previous one. Normalized to roughly [-1, 1].
+    pub fn fractal(&self, x: f32, y: f32, octaves: usize, persistence: f32) -> f32 {
+        let mut total = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        let mut max_value = 0.0;
+        for _ in 0..octaves {
+            total += self.noise(x * frequency, y * frequency) * amplitude;
+            max_value += amplitude;
+            amplitude *= persistence;
+            frequency *= 2.0;
+        }
+        total / max_value
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+fn gradient(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 0x7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+/// Combine the seed components into a single PCG seed
+fn seed_to_u128(seed: [f32; 3]) -> u128 {
+    u128::from(seed[0].to_bits()) << 64
+        | u128::from(seed[1].to_bits()) << 32
+        | u128::from(seed[2].to_bits())
+}
+
+/// Generate terrain from a seed. The result only depends on the arguments, so the server and
+/// every client arrive at the same grid.
+///
+/// Air cells are 0, solid cells hold a non-zero intensity derived from the noise.
+pub fn generate_terrain(width: usize, height: usize, seed: [f32; 3]) -> Grid<Reality> {
+    let perlin = Perlin::new(seed_to_u128(seed));
+    let mut grid = Grid::new();
+    grid.resize(width, height);
+    for j in 0..height {
+        for i in 0..width {
+            let value = perlin.fractal(
+                i as f32 * SCALE + seed[0],
+                j as f32 * SCALE + seed[1],
+                OCTAVES,
+                PERSISTENCE,
+            );
+            if value > AIR_THRESHOLD {
+                grid.set(i, j, (128.0 + value.min(1.0) * 127.0) as Reality);
+            }
+        }
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_terrain() {
+        let a = generate_terrain(100, 80, [1.0, 2.0, 3.0]);
+        let b = generate_terrain(100, 80, [1.0, 2.0, 3.0]);
+        for j in 0..80 {
+            for i in 0..100 {
+                assert_eq![a.get(i, j), b.get(i, j)];
+            }
+        }
+    }
+
+    #[test]
+    fn different_seed_gives_different_terrain() {
+        let a = generate_terrain(100, 100, [0.0, 0.0, 0.0]);
+        let b = generate_terrain(100, 100, [0.0, 0.0, 1.0]);
+        let differences = (0..100)
+            .flat_map(|j| (0..100).map(move |i| (i, j)))
+            .filter(|(i, j)| a.get(*i, *j) != b.get(*i, *j))
+            .count();
+        assert![differences > 0];
+    }
+
+    #[test]
+    fn terrain_has_requested_size() {
+        let grid = generate_terrain(13, 7, [0.0, 0.0, 0.0]);
+        assert_eq![(13, 7), grid.get_size()];
+    }
+
+    #[test]
+    fn terrain_contains_air_and_solid() {
+        let grid = generate_terrain(200, 200, [0.0, 0.0, 0.0]);
+        let solid = (0..200)
+            .flat_map(|j| (0..200).map(move |i| (i, j)))
+            .filter(|(i, j)| *grid.get(*i, *j).unwrap() > 0)
+            .count();
+        assert![solid > 0];
+        assert![solid < 200 * 200];
+    }
+
+    #[test]
+    fn noise_is_zero_on_lattice_points() {
+        let perlin = Perlin::new(0);
+        assert_eq![0.0, perlin.noise(3.0, 7.0)];
+        assert_eq![0.0, perlin.noise(-2.0, 5.0)];
+    }
+}